@@ -0,0 +1,92 @@
+use crate::cpu::CpuInfo;
+use crate::msr::{MsrCores, RAPL_COUNTER_WIDTH};
+use crate::source::{Domain, EnergySource, Units};
+use crate::topology::Topology;
+
+const AMD_MSR_PWR_UNIT:         u64 = 0xC0010299;
+const AMD_MSR_CORE_ENERGY:      u64 = 0xC001029A;
+const AMD_MSR_PACKAGE_ENERGY:   u64 = 0xC001029B;
+
+// Zen (17h), Hygon Dhyana (18h), Zen 3/4 (19h) and Zen 5 (1Ah) all carry the RAPL MSRs above.
+const SUPPORTED_FAMILIES: &[u32] = &[0x17, 0x18, 0x19, 0x1A];
+
+#[derive(Debug)]
+pub struct AmdCpuContext {
+    msr: MsrCores,
+
+    // One CPU of every socket, where the package counter is read
+    packages: MsrCores,
+
+    topology: Topology
+}
+
+/// Returns `None` if the CPU has the AMD energy MSRs, or the reason why it cannot be measured.
+pub fn unsupported_reason(cpu: &CpuInfo) -> Option<String> {
+    if SUPPORTED_FAMILIES.contains(&cpu.family) {
+        None
+    } else {
+        Some(format!("AMD family {:#04x} has no core/package energy MSRs, only Zen (family 17h and later) is supported.", cpu.family))
+    }
+}
+
+impl AmdCpuContext {
+    /// Opens the MSRs of one SMT sibling of every online physical core, and of one CPU of every socket.
+    pub fn new() -> Result<AmdCpuContext, std::io::Error> {
+        let topology = Topology::detect()?;
+
+        Ok(AmdCpuContext {
+            msr: MsrCores::open(&topology.core_cpus(), AMD_MSR_PWR_UNIT)?,
+            packages: MsrCores::open(&topology.package_cpus(), AMD_MSR_PWR_UNIT)?,
+            topology
+        })
+    }
+}
+
+impl EnergySource for AmdCpuContext {
+    fn name(&self) -> &'static str { "amd-msr" }
+
+    fn domains(&self) -> Vec<Domain> { vec![Domain::Package, Domain::Core] }
+
+    fn get_cores(&self) -> usize { self.msr.get_cores() }
+
+    fn get_packages(&self) -> usize { self.packages.get_cores() }
+
+    fn read_raw_per_package(&self, domain: Domain) -> Result<Vec<u64>, std::io::Error> {
+        match domain {
+            Domain::Package => self.packages.read_counters(AMD_MSR_PACKAGE_ENERGY),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("No per-package {domain} energy counter")))
+        }
+    }
+
+    fn all_core_raw(&self) -> Result<Vec<u64>, std::io::Error> {
+        self.msr.read_counters(AMD_MSR_CORE_ENERGY)
+    }
+
+    // Core counters are per core, their per-package energy is the sum over the cores of each package.
+    fn read_energy_per_package(&self, domain: Domain) -> Result<Vec<f64>, std::io::Error> {
+        match domain {
+            Domain::Core => {
+                let mut result = vec![0.0_f64; self.get_packages()];
+                for (core, energy) in self.all_core_energy()?.into_iter().enumerate() {
+                    if let Some(package) = self.topology.package_of(self.msr.cpu(core)) {
+                        result[package] += energy;
+                    }
+                }
+
+                Ok(result)
+            }
+            _ => {
+                let unit = self.energy_unit(domain);
+                Ok(self.read_raw_per_package(domain)?.into_iter().map(|raw| raw as f64 * unit).collect())
+            }
+        }
+    }
+
+    fn core_of_cpu(&self, cpu: usize) -> Option<usize> {
+        self.topology.core_of(cpu)
+    }
+
+    fn counter_width(&self, _domain: Domain) -> u32 { RAPL_COUNTER_WIDTH }
+
+    fn units(&self) -> Units { self.msr.units(0) }
+}
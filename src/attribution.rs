@@ -0,0 +1,374 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sample::CollectedPoint;
+
+/// The built-in attribution models, selected with `--model`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Model {
+    // CPU energy times the share of all CPUs the target used
+    CpuTime,
+
+    // CPU energy above the idle power times the target's share of the busy time of all CPUs
+    Dynamic,
+
+    // Energy of every counter times the share of its CPUs' busy time that was the target's
+    PerCore,
+
+    // Energy of every counter split over its physical cores by how long they were active, then over SMT siblings
+    Smt
+}
+
+/// What the models know about the machine the samples were taken on.
+#[derive(Debug, Clone, Default)]
+pub struct Machine {
+    // Logical CPUs
+    pub ncpus: usize,
+
+    // Index of the core counter, or the package without one, covering every logical CPU
+    pub counter_of_cpu: Vec<Option<usize>>,
+
+    // Index of the physical core of every logical CPU
+    pub core_of_cpu: Vec<Option<usize>>
+}
+
+/// Two consecutive samples, and the CPU time the target used between them.
+#[derive(Debug, Clone, Copy)]
+pub struct Interval<'a> {
+    pub last: &'a CollectedPoint,
+
+    pub point: &'a CollectedPoint,
+
+    // Nanoseconds
+    pub target_cpu_time: u64
+}
+
+/// Decides how much of the energy of an interval the target is responsible for.
+pub trait AttributionModel {
+    fn model(&self) -> Model;
+
+    /// Energy of the interval attributed to the target, in Joules.
+    fn attribute(&self, interval: &Interval) -> f64;
+}
+
+/// `cpu energy * target CPU time / (CPUs * interval)`: all energy is taken to be dynamic and to scale with CPU time.
+#[derive(Debug, Clone)]
+pub struct CpuTimeShare {
+    ncpus: usize
+}
+
+/// `(cpu energy - idle power * interval) * target CPU time / busy time of all CPUs`: the target is charged for the
+/// energy above idle, by its share of what the CPUs actually did.
+#[derive(Debug, Clone)]
+pub struct DynamicShare {
+    ncpus: usize,
+
+    // Watts
+    idle_power: f64
+}
+
+/// Charges the energy of every counter by the target's share of the busy time of the CPUs it covers.
+#[derive(Debug, Clone)]
+pub struct PerCoreShare {
+    machine: Machine
+}
+
+/// Splits the energy of every counter over its physical cores by how long each was active, counting a core once
+/// however many of its SMT siblings were busy, then charges the target its share of the busy time of each core.
+#[derive(Debug, Clone)]
+pub struct SmtShare {
+    machine: Machine
+}
+
+impl Model {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::CpuTime => "cpu-time",
+            Model::Dynamic => "dynamic",
+            Model::PerCore => "per-core",
+            Model::Smt => "smt"
+        }
+    }
+
+    /// Whether the model needs the idle power of the CPU.
+    pub fn needs_idle_power(&self) -> bool {
+        *self == Model::Dynamic
+    }
+
+    /// Whether the model looks at which CPUs the target ran on.
+    pub fn needs_placement(&self) -> bool {
+        matches!(self, Model::PerCore | Model::Smt)
+    }
+
+    /// Whether the model looks at the busy time of every CPU.
+    pub fn needs_busy_times(&self) -> bool {
+        matches!(self, Model::Dynamic | Model::PerCore | Model::Smt)
+    }
+
+    pub fn build(&self, machine: &Machine, idle_power: Option<f64>) -> Result<Box<dyn AttributionModel>, std::io::Error> {
+        Ok(match self {
+            Model::CpuTime => Box::new(CpuTimeShare { ncpus: machine.ncpus }),
+            Model::Dynamic => Box::new(DynamicShare {
+                ncpus: machine.ncpus,
+                idle_power: idle_power.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "The dynamic model needs the idle power of the CPU"))?
+            }),
+            Model::PerCore => Box::new(PerCoreShare { machine: machine.clone() }),
+            Model::Smt => Box::new(SmtShare { machine: machine.clone() })
+        })
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// Difference of two cumulative per-CPU readings, a CPU missing from either reading counting as 0
+fn cpu_delta(after: &[u64], before: &[u64], cpu: usize) -> f64 {
+    after.get(cpu).unwrap_or(&0).saturating_sub(*before.get(cpu).unwrap_or(&0)) as f64
+}
+
+// Busy time of every CPU during the interval, in nanoseconds, None if it was not read
+fn busy(interval: &Interval) -> Option<Vec<f64>> {
+    let Interval { last, point, .. } = interval;
+    if point.busy_cpus.is_empty() {
+        return None;
+    }
+
+    Some((0..point.busy_cpus.len()).map(|cpu| cpu_delta(&point.busy_cpus, &last.busy_cpus, cpu)).collect())
+}
+
+// CPU time of the target on every CPU during the interval, in nanoseconds. The CPU time is spread over the CPUs as the
+// sampled threads were, so short-lived threads still count. None when nothing tells where the target ran, e.g. in
+// traces without placement.
+fn placement(interval: &Interval) -> Option<Vec<f64>> {
+    let Interval { last, point, target_cpu_time } = interval;
+
+    let placed: Vec<f64> = (0..point.sched_cpus.len()).map(|cpu| cpu_delta(&point.sched_cpus, &last.sched_cpus, cpu)).collect();
+    let placed_total: f64 = placed.iter().sum();
+    if placed_total == 0.0 {
+        return None;
+    }
+
+    Some(placed.iter().map(|p| *target_cpu_time as f64 * p / placed_total).collect())
+}
+
+// Energy of every counter: the core counters, or the packages without them
+fn counters(point: &CollectedPoint) -> &[f64] {
+    if point.cores.is_empty() { &point.sockets } else { &point.cores }
+}
+
+impl CpuTimeShare {
+    pub fn new(ncpus: usize) -> CpuTimeShare {
+        CpuTimeShare { ncpus }
+    }
+}
+
+impl AttributionModel for CpuTimeShare {
+    fn model(&self) -> Model { Model::CpuTime }
+
+    fn attribute(&self, interval: &Interval) -> f64 {
+        let dur = interval.point.time - interval.last.time;
+
+        // The CPU time of the target and the sampling clock can disagree a little, never charge more than all of it
+        interval.point.cpu_power * (interval.target_cpu_time as f64 / (self.ncpus as f64 * dur as f64)).min(1.0)
+    }
+}
+
+impl AttributionModel for DynamicShare {
+    fn model(&self) -> Model { Model::Dynamic }
+
+    fn attribute(&self, interval: &Interval) -> f64 {
+        let dur = interval.point.time - interval.last.time;
+        let dynamic = (interval.point.cpu_power - self.idle_power * dur as f64 / 1000000000.0).max(0.0);
+        let target = interval.target_cpu_time as f64;
+
+        // Busy time is counted in clock ticks, the target can seem busier than the CPUs over a short interval
+        match busy(interval) {
+            Some(busy) => dynamic * target / busy.iter().sum::<f64>().max(target),
+            None => dynamic * (target / (self.ncpus as f64 * dur as f64)).min(1.0)
+        }
+    }
+}
+
+impl AttributionModel for PerCoreShare {
+    fn model(&self) -> Model { Model::PerCore }
+
+    fn attribute(&self, interval: &Interval) -> f64 {
+        let energies = counters(interval.point);
+        let (Some(placed), Some(busy)) = (placement(interval), busy(interval)) else {
+            return CpuTimeShare::new(self.machine.ncpus).attribute(interval);
+        };
+
+        let mut target = vec![0.0_f64; energies.len()];
+        let mut counter_busy = vec![0.0_f64; energies.len()];
+        for (cpu, counter) in self.machine.counter_of_cpu.iter().enumerate() {
+            let Some(counter) = counter.filter(|c| *c < energies.len()) else { continue };
+
+            target[counter] += placed.get(cpu).unwrap_or(&0.0);
+            counter_busy[counter] += busy.get(cpu).unwrap_or(&0.0);
+        }
+
+        energies.iter().zip(target.iter().zip(&counter_busy))
+            .filter(|(_, (target, _))| **target > 0.0)
+            .map(|(energy, (target, busy))| energy * target / busy.max(*target))
+            .sum()
+    }
+}
+
+impl AttributionModel for SmtShare {
+    fn model(&self) -> Model { Model::Smt }
+
+    fn attribute(&self, interval: &Interval) -> f64 {
+        let energies = counters(interval.point);
+        let (Some(placed), Some(busy)) = (placement(interval), busy(interval)) else {
+            return CpuTimeShare::new(self.machine.ncpus).attribute(interval);
+        };
+        let dur = (interval.point.time - interval.last.time) as f64;
+
+        let ncores = self.machine.core_of_cpu.iter().flatten().max().map_or(0, |core| core + 1);
+        let mut core_counter = vec![None; ncores];
+        let mut core_target = vec![0.0_f64; ncores];
+        let mut core_busy = vec![0.0_f64; ncores];
+        let mut core_active = vec![0.0_f64; ncores];
+
+        for (cpu, core) in self.machine.core_of_cpu.iter().enumerate() {
+            let Some(core) = *core else { continue };
+            let target = *placed.get(cpu).unwrap_or(&0.0);
+            let cpu_busy = busy.get(cpu).unwrap_or(&0.0).max(target);
+
+            core_counter[core] = self.machine.counter_of_cpu.get(cpu).copied().flatten().filter(|c| *c < energies.len());
+            core_target[core] += target;
+            core_busy[core] += cpu_busy;
+
+            // A core is active at least as long as its busiest sibling, and never longer than the interval
+            core_active[core] = core_active[core].max(cpu_busy.min(dur));
+        }
+
+        let mut counter_active = vec![0.0_f64; energies.len()];
+        for (counter, active) in core_counter.iter().zip(&core_active) {
+            if let Some(counter) = counter {
+                counter_active[*counter] += active;
+            }
+        }
+
+        (0..ncores)
+            .filter(|core| core_target[*core] > 0.0)
+            .filter_map(|core| {
+                let counter = core_counter[core]?;
+                let core_share = core_active[core] / counter_active[counter];
+
+                Some(energies[counter] * core_share * core_target[core] / core_busy[core])
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const S: u64 = 1000000000;
+
+    // One second on four CPUs: two physical cores with two SMT siblings each, CPUs 0 and 1 on core 0
+    fn machine(counter_of_cpu: &[usize]) -> Machine {
+        Machine {
+            ncpus: 4,
+            counter_of_cpu: counter_of_cpu.iter().map(|c| Some(*c)).collect(),
+            core_of_cpu: vec![Some(0), Some(0), Some(1), Some(1)]
+        }
+    }
+
+    fn sample(time: u64, cores: &[f64], sched_cpus: &[u64], busy_cpus: &[u64]) -> CollectedPoint {
+        CollectedPoint {
+            time,
+            package: cores.iter().sum::<f64>() + 5.0,
+            sockets: vec![cores.iter().sum::<f64>() + 5.0],
+            cpu_power: cores.iter().sum(),
+            cores: cores.to_vec(),
+            other: Vec::new(),
+            sched: sched_cpus.iter().sum(),
+            sched_cpus: sched_cpus.to_vec(),
+            busy_cpus: busy_cpus.to_vec()
+        }
+    }
+
+    // The energy attributed over one second ending in `point`, after a sample with every counter at 0
+    fn attribute(model: &dyn AttributionModel, point: &CollectedPoint, target_cpu_time: u64) -> f64 {
+        let last = sample(0, &[], &[0; 4], &[0; 4]);
+
+        model.attribute(&Interval { last: &last, point, target_cpu_time })
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn cpu_time() {
+        let model = CpuTimeShare::new(4);
+
+        // Two of four CPUs for the whole second
+        assert_close(attribute(&model, &sample(S, &[40.0], &[], &[]), 2 * S), 20.0);
+        // More CPU time than the four CPUs had
+        assert_close(attribute(&model, &sample(S, &[40.0], &[], &[]), 5 * S), 40.0);
+    }
+
+    #[test]
+    fn dynamic_subtracts_idle_power() {
+        let model = Model::Dynamic.build(&machine(&[0, 0, 0, 0]), Some(10.0)).unwrap();
+        let busy = [S, S / 2, S / 2, 0];
+
+        // 30 J above idle, the target was half of the 2 s the CPUs were busy
+        assert_close(attribute(model.as_ref(), &sample(S, &[40.0], &[], &busy), S), 15.0);
+        // Below idle, nothing is dynamic
+        assert_close(attribute(model.as_ref(), &sample(S, &[5.0], &[], &busy), S), 0.0);
+        // Without busy times, by the share of all CPUs
+        assert_close(attribute(model.as_ref(), &sample(S, &[40.0], &[], &[]), S), 7.5);
+        // The target seems busier than the CPUs, and is charged all of the dynamic energy
+        assert_close(attribute(model.as_ref(), &sample(S, &[40.0], &[], &busy), 3 * S), 30.0);
+
+        assert!(Model::Dynamic.build(&machine(&[0, 0, 0, 0]), None).is_err());
+    }
+
+    #[test]
+    fn per_core_two_cores() {
+        // A counter per physical core, as on AMD
+        let model = Model::PerCore.build(&machine(&[0, 0, 1, 1]), None).unwrap();
+        let busy = [S, S / 2, S / 2, S / 2];
+
+        // 0.6 s of the 1.5 s core 0 was busy, 0.2 s of the 1 s of core 1
+        let point = sample(S, &[12.0, 8.0], &[600000000, 0, 200000000, 0], &busy);
+        assert_close(attribute(model.as_ref(), &point, 800000000), 12.0 * 0.6 / 1.5 + 8.0 * 0.2);
+
+        // The CPU time spreads over the CPUs as the sampled threads did
+        assert_close(attribute(model.as_ref(), &point, 1600000000), 12.0 * 1.2 / 1.5 + 8.0 * 0.4);
+
+        // More than the busy time of core 0, which is all the target's
+        let point = sample(S, &[12.0, 8.0], &[S, 0, 0, 0], &busy);
+        assert_close(attribute(model.as_ref(), &point, 2 * S), 12.0);
+
+        // Without placement, by CPU time
+        let point = sample(S, &[12.0, 8.0], &[], &busy);
+        assert_close(attribute(model.as_ref(), &point, 2 * S), 10.0);
+    }
+
+    #[test]
+    fn smt_splits_siblings() {
+        // One counter for the package, as Intel's PP0
+        let model = Model::Smt.build(&machine(&[0, 0, 0, 0]), None).unwrap();
+
+        // Core 0 was active 1 s and core 1 0.25 s, so core 0 used 0.8 of the energy, and the target on CPU 0 a third
+        // of the 1.5 s its siblings were busy
+        let point = sample(S, &[20.0], &[S / 2, 0, 0, 0], &[S, S / 2, S / 4, 0]);
+        assert_close(attribute(model.as_ref(), &point, S / 2), 16.0 / 3.0);
+
+        // Ticks make CPU 0 seem busy longer than the interval, and the target busier than CPU 0: core 0 is active
+        // for the whole second, and the target is charged all of it
+        let point = sample(S, &[20.0], &[3 * S / 2, 0, 0, 0], &[6 * S / 5, 0, S / 4, 0]);
+        assert_close(attribute(model.as_ref(), &point, 3 * S / 2), 16.0);
+    }
+}
@@ -0,0 +1,202 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::report::DomainValues;
+use crate::source::{Domain, EnergySource};
+
+/// Idle power of every domain, measured while the target was not running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Baseline {
+    // Length of the calibration window, or of all of them together
+    pub duration_ns: u64,
+
+    // Average power in Watts, with no `attributed` part
+    pub power: DomainValues
+}
+
+// Energy counted by every counter between two readings
+fn deltas(ctx: &dyn EnergySource, domain: Domain, before: &[u64], after: &[u64]) -> Vec<f64> {
+    before.iter().zip(after).map(|(b, a)| ctx.energy_delta(domain, *b, *a)).collect()
+}
+
+fn add(totals: &mut Vec<f64>, deltas: &[f64]) {
+    totals.resize(deltas.len().max(totals.len()), 0.0);
+    for (total, delta) in totals.iter_mut().zip(deltas) {
+        *total += delta;
+    }
+}
+
+impl Baseline {
+    /// Samples every domain for `duration`, reading the counters every `period` so none of them wraps unnoticed.
+    pub fn measure(ctx: &dyn EnergySource, duration: Duration, period: Duration, other_domains: &[Domain]) -> Result<Baseline, std::io::Error> {
+        let has_core = ctx.domains().contains(&Domain::Core);
+
+        let mut package_last = ctx.read_raw_per_package(Domain::Package)?;
+        let mut cores_last = ctx.all_core_raw()?;
+        let mut other_last = other_domains.iter().map(|d| ctx.read_raw_per_package(*d)).collect::<Result<Vec<_>, _>>()?;
+        let start = Instant::now();
+
+        let mut sockets = Vec::<f64>::new();
+        let mut cores = Vec::<f64>::new();
+        let mut other = vec![0.0_f64; other_domains.len()];
+
+        while start.elapsed() < duration {
+            thread::sleep(period.min(duration.saturating_sub(start.elapsed())));
+
+            let package = ctx.read_raw_per_package(Domain::Package)?;
+            let core = ctx.all_core_raw()?;
+            add(&mut sockets, &deltas(ctx, Domain::Package, &package_last, &package));
+            add(&mut cores, &deltas(ctx, Domain::Core, &cores_last, &core));
+            package_last = package;
+            cores_last = core;
+
+            for ((domain, last), total) in other_domains.iter().zip(other_last.iter_mut()).zip(other.iter_mut()) {
+                let energy = ctx.read_raw_per_package(*domain)?;
+                *total += deltas(ctx, *domain, last, &energy).iter().sum::<f64>();
+                *last = energy;
+            }
+        }
+
+        let duration_ns = start.elapsed().as_nanos() as u64;
+        let package: f64 = sockets.iter().sum();
+        let energy = DomainValues {
+            package,
+            cpu: if has_core { cores.iter().sum() } else { package },
+            sockets,
+            cores,
+            attributed: None,
+            domains: other_domains.iter().map(|d| d.name().to_owned()).zip(other).collect()
+        };
+
+        Ok(Baseline {
+            duration_ns,
+            power: energy.per_second(duration_ns)
+        })
+    }
+
+    /// The baseline over several calibration windows, each weighted by its length.
+    pub fn combine(baselines: &[Baseline]) -> Option<Baseline> {
+        let duration_ns: u64 = baselines.iter().map(|b| b.duration_ns).sum();
+        if duration_ns == 0 {
+            return baselines.first().cloned();
+        }
+
+        let weight = |b: &Baseline| b.duration_ns as f64 / duration_ns as f64;
+        let mut power = DomainValues::default();
+        for baseline in baselines {
+            let w = weight(baseline);
+
+            power.package += baseline.power.package * w;
+            power.cpu += baseline.power.cpu * w;
+            add(&mut power.sockets, &baseline.power.sockets.iter().map(|p| p * w).collect::<Vec<_>>());
+            add(&mut power.cores, &baseline.power.cores.iter().map(|p| p * w).collect::<Vec<_>>());
+            for (domain, p) in &baseline.power.domains {
+                *power.domains.entry(domain.clone()).or_default() += p * w;
+            }
+        }
+
+        Some(Baseline { duration_ns, power })
+    }
+
+    /// Energy above the baseline, of the domains in `energy` measured over `duration_ns`. Noise can make it negative
+    /// for a domain the target barely uses.
+    pub fn subtract(&self, energy: &DomainValues, duration_ns: u64) -> DomainValues {
+        let secs = duration_ns as f64 / 1000000000.0;
+        let net = |energy: f64, power: f64| energy - power * secs;
+
+        DomainValues {
+            package: net(energy.package, self.power.package),
+            sockets: energy.sockets.iter().enumerate().map(|(i, e)| net(*e, *self.power.sockets.get(i).unwrap_or(&0.0))).collect(),
+            cpu: net(energy.cpu, self.power.cpu),
+            cores: energy.cores.iter().enumerate().map(|(i, e)| net(*e, *self.power.cores.get(i).unwrap_or(&0.0))).collect(),
+            attributed: None,
+            domains: energy.domains.iter().map(|(d, e)| (d.clone(), net(*e, *self.power.domains.get(d).unwrap_or(&0.0)))).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn baseline(duration_ns: u64, package: f64, sockets: &[f64], cores: &[f64], dram: f64) -> Baseline {
+        Baseline {
+            duration_ns,
+            power: DomainValues {
+                package,
+                sockets: sockets.to_vec(),
+                cpu: cores.iter().sum(),
+                cores: cores.to_vec(),
+                attributed: None,
+                domains: [("dram".to_owned(), dram)].into_iter().collect()
+            }
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn combine_weights_by_length() {
+        // 1 s before and 3 s after the target ran
+        let combined = Baseline::combine(&[
+            baseline(1000000000, 10.0, &[6.0, 4.0], &[2.0, 2.0], 1.0),
+            baseline(3000000000, 14.0, &[8.0, 6.0], &[4.0, 2.0], 3.0)
+        ]).unwrap();
+
+        assert_eq!(combined.duration_ns, 4000000000);
+        assert_close(combined.power.package, 13.0);
+        assert_close(combined.power.sockets[0], 7.5);
+        assert_close(combined.power.sockets[1], 5.5);
+        assert_close(combined.power.cpu, 5.5);
+        assert_close(combined.power.cores[0], 3.5);
+        assert_close(combined.power.cores[1], 2.0);
+        assert_close(combined.power.domains["dram"], 2.5);
+        assert!(combined.power.attributed.is_none());
+    }
+
+    #[test]
+    fn combine_edge_cases() {
+        assert!(Baseline::combine(&[]).is_none());
+
+        let one = baseline(2000000000, 10.0, &[10.0], &[4.0], 1.0);
+        let combined = Baseline::combine(std::slice::from_ref(&one)).unwrap();
+        assert_eq!(combined.duration_ns, one.duration_ns);
+        assert_close(combined.power.package, 10.0);
+
+        // Windows too short to be timed are taken as they are
+        let combined = Baseline::combine(&[baseline(0, 8.0, &[8.0], &[4.0], 1.0), baseline(0, 20.0, &[20.0], &[4.0], 1.0)]).unwrap();
+        assert_close(combined.power.package, 8.0);
+    }
+
+    #[test]
+    fn subtract_over_duration() {
+        let idle = baseline(1000000000, 10.0, &[6.0, 4.0], &[2.0, 2.0], 1.0);
+        let energy = DomainValues {
+            package: 50.0,
+            sockets: vec![30.0, 20.0],
+            cpu: 30.0,
+            cores: vec![25.0, 5.0],
+            attributed: Some(12.0),
+            domains: [("dram".to_owned(), 4.0), ("psys".to_owned(), 60.0)].into_iter().collect()
+        };
+
+        // 2 s at the idle power
+        let net = idle.subtract(&energy, 2000000000);
+
+        assert_close(net.package, 30.0);
+        assert_eq!(net.sockets, vec![18.0, 12.0]);
+        assert_close(net.cpu, 22.0);
+        assert_eq!(net.cores, vec![21.0, 1.0]);
+        assert_close(net.domains["dram"], 2.0);
+        // No idle power for the domain, and nothing attributed
+        assert_close(net.domains["psys"], 60.0);
+        assert!(net.attributed.is_none());
+
+        // Noise can take a barely used domain below the baseline
+        assert!(idle.subtract(&DomainValues { cores: vec![1.0, 1.0], ..energy }, 2000000000).cores[0] < 0.0);
+    }
+}
@@ -0,0 +1,276 @@
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use crate::util::invalid_data;
+
+/// CPU usage of a cgroup from its `cpu.stat`, in microseconds. Covers every process that ever ran in it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuStat {
+    pub usage_usec: u64,
+
+    pub user_usec: u64,
+
+    pub system_usec: u64
+}
+
+/// A cgroup v2 directory. Cgroups created by `create` are removed again by `remove`, or when dropped.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+
+    // Whether we created it and have to clean it up
+    owned: bool
+}
+
+/// Where the cgroup v2 hierarchy is mounted, usually `/sys/fs/cgroup`, or `/sys/fs/cgroup/unified` on hybrid systems.
+pub fn mount_point() -> Result<PathBuf, std::io::Error> {
+    let mounts = fs::read_to_string("/proc/self/mounts")?;
+
+    mounts.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.get(2) == Some(&"cgroup2"))
+        .and_then(|fields| fields.get(1).map(PathBuf::from))
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No cgroup v2 hierarchy is mounted"))
+}
+
+/// Path of the cgroup v2 a process belongs to, relative to the mount point, e.g. `/user.slice/user-1000.slice`.
+pub fn cgroup_of(pid: &str) -> Result<String, std::io::Error> {
+    let content = fs::read_to_string(format!("/proc/{pid}/cgroup"))?;
+
+    unified_path(&content)
+        .map(|path| path.to_owned())
+        .ok_or_else(|| invalid_data(format!("Process {pid} is not in a cgroup v2 hierarchy")))
+}
+
+// The cgroup v2 path in the contents of /proc/<pid>/cgroup. The unified hierarchy is the one with ID 0 and no
+// controllers.
+fn unified_path(content: &str) -> Option<&str> {
+    content.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+// Every cgroup below `dir` whose path satisfies `matches`
+fn find(dir: &Path, matches: &dyn Fn(&Path) -> bool, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+
+        let path = entry.path();
+        if matches(&path) {
+            found.push(path.clone());
+        }
+        find(&path, matches, found);
+    }
+}
+
+// The only cgroup matching, or an error naming `what` if there is none or several
+fn find_one(what: &str, matches: &dyn Fn(&Path) -> bool) -> Result<Cgroup, std::io::Error> {
+    let mut found = Vec::<PathBuf>::new();
+    find(&mount_point()?, matches, &mut found);
+
+    match found.len() {
+        0 => Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No cgroup found for {what}"))),
+        1 => Cgroup::open(found.remove(0)),
+        _ => Err(invalid_data(format!("{what} matches several cgroups: {}", found.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", "))))
+    }
+}
+
+// Container ID of a cgroup created by a container runtime, e.g. `docker-<id>.scope` with the systemd cgroup driver,
+// or `docker/<id>` with the cgroupfs driver
+fn container_id(path: &Path) -> Option<&str> {
+    let name = path.file_name()?.to_str()?;
+
+    let id = match name.strip_suffix(".scope") {
+        Some(scope) => ["docker-", "libpod-", "cri-containerd-", "crio-"].iter().find_map(|prefix| scope.strip_prefix(prefix))?,
+        None if path.parent()?.file_name().is_some_and(|p| p == "docker" || p == "libpod_parent") => name,
+        None => return None
+    };
+
+    id.chars().all(|c| c.is_ascii_hexdigit()).then_some(id)
+}
+
+impl CpuStat {
+    /// Parses the contents of a `cpu.stat` file. Fails without `usage_usec`, which every cgroup v2 has.
+    pub fn parse(content: &str) -> Result<CpuStat, std::io::Error> {
+        let mut stat = CpuStat::default();
+        let mut has_usage = false;
+
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once(' ') else { continue };
+            let Ok(value) = value.trim().parse::<u64>() else { continue };
+
+            match key {
+                "usage_usec" => {
+                    stat.usage_usec = value;
+                    has_usage = true;
+                }
+                "user_usec" => stat.user_usec = value,
+                "system_usec" => stat.system_usec = value,
+                _ => { }
+            }
+        }
+
+        if !has_usage {
+            return Err(invalid_data("cpu.stat has no usage_usec"));
+        }
+
+        Ok(stat)
+    }
+
+    /// Total CPU time in nanoseconds.
+    pub fn usage_ns(&self) -> u64 {
+        self.usage_usec * 1000
+    }
+}
+
+impl Cgroup {
+    /// An existing cgroup, which is left alone when dropped. A path that is not a cgroup directory itself is taken
+    /// relative to the cgroup v2 mount point, as in `/proc/<pid>/cgroup`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Cgroup, std::io::Error> {
+        let mut path = path.as_ref().to_path_buf();
+        if !path.join("cgroup.procs").exists() {
+            path = mount_point()?.join(path.strip_prefix("/").unwrap_or(&path));
+        }
+        if !path.join("cgroup.procs").exists() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} is not a cgroup v2 directory", path.display())));
+        }
+
+        Ok(Cgroup { path, owned: false })
+    }
+
+    /// Creates a new cgroup below the one we run in. This works as root, or when our cgroup has been delegated to us,
+    /// as systemd does for user sessions and services with `Delegate=yes`.
+    pub fn create(name: &str) -> Result<Cgroup, std::io::Error> {
+        let parent = mount_point()?.join(cgroup_of("self")?.trim_start_matches('/'));
+        let path = parent.join(name);
+
+        fs::create_dir(&path).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+
+        Ok(Cgroup { path, owned: true })
+    }
+
+    /// The cgroup of a Docker, Podman, containerd or CRI-O container, from its full ID or a unique prefix of it.
+    pub fn container(id: &str) -> Result<Cgroup, std::io::Error> {
+        find_one(&format!("container {id}"), &|path| container_id(path).is_some_and(|c| !id.is_empty() && c.starts_with(id)))
+    }
+
+    /// The cgroup of a systemd unit. A name without a suffix is taken to be a service.
+    pub fn unit(name: &str) -> Result<Cgroup, std::io::Error> {
+        let name = if name.contains('.') { name.to_owned() } else { format!("{name}.service") };
+
+        find_one(&format!("unit {name}"), &|path| path.file_name().is_some_and(|n| *n == *name))
+    }
+
+    /// Whether the cgroup still exists. Container runtimes and systemd remove theirs when the container or unit stops.
+    pub fn exists(&self) -> bool {
+        self.path.join("cgroup.procs").exists()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `cgroup.procs` opened for writing. Writing "0" to it moves the writing process into the cgroup, which is how a
+    /// child joins it between fork and exec.
+    pub fn procs_file(&self) -> Result<File, std::io::Error> {
+        OpenOptions::new().write(true).open(self.path.join("cgroup.procs"))
+    }
+
+    /// Processes currently in the cgroup, not counting its child cgroups.
+    pub fn procs(&self) -> Result<Vec<u32>, std::io::Error> {
+        let content = fs::read_to_string(self.path.join("cgroup.procs"))?;
+
+        Ok(content.split_whitespace().filter_map(|p| p.parse::<u32>().ok()).collect())
+    }
+
+    /// Threads in the cgroup and all its child cgroups.
+    pub fn threads(&self) -> Vec<u32> {
+        let mut dirs = vec![self.path.clone()];
+        find(&self.path, &|_| true, &mut dirs);
+
+        dirs.iter()
+            .filter_map(|dir| fs::read_to_string(dir.join("cgroup.threads")).ok())
+            .flat_map(|content| content.split_whitespace().filter_map(|t| t.parse::<u32>().ok()).collect::<Vec<_>>())
+            .collect()
+    }
+
+    pub fn cpu_stat(&self) -> Result<CpuStat, std::io::Error> {
+        CpuStat::parse(&fs::read_to_string(self.path.join("cpu.stat"))?)
+    }
+
+    /// Removes a cgroup we created. Processes left behind, e.g. daemons started by the target, are moved back to the
+    /// parent cgroup first, since a populated cgroup cannot be removed.
+    pub fn remove(mut self) -> Result<(), std::io::Error> {
+        self.cleanup()
+    }
+
+    fn cleanup(&mut self) -> Result<(), std::io::Error> {
+        if !self.owned {
+            return Ok(());
+        }
+        self.owned = false;
+
+        if let Some(parent) = self.path.parent() {
+            for pid in self.procs().unwrap_or_default() {
+                let _ = fs::write(parent.join("cgroup.procs"), pid.to_string());
+            }
+        }
+
+        fs::remove_dir(&self.path)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let _ = self.cleanup();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "3f6a5c1be8d24e0f9a7b2c3d4e5f60718293a4b5c6d7e8f9a0b1c2d3e4f50617";
+
+    // The container ID in the contents of /proc/<pid>/cgroup
+    fn container_of(content: &str) -> Option<&str> {
+        container_id(Path::new(unified_path(content)?))
+    }
+
+    #[test]
+    fn container_ids() {
+        // Docker with the systemd and the cgroupfs driver
+        assert_eq!(container_of(&format!("0::/system.slice/docker-{ID}.scope\n")), Some(ID));
+        assert_eq!(container_of(&format!("0::/docker/{ID}\n")), Some(ID));
+        // containerd and CRI-O under Kubernetes
+        assert_eq!(container_of(&format!("0::/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1a2b.slice/cri-containerd-{ID}.scope\n")), Some(ID));
+        assert_eq!(container_of(&format!("0::/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod1a2b.slice/crio-{ID}.scope\n")), Some(ID));
+        // Podman, rootless and with the cgroupfs manager
+        assert_eq!(container_of(&format!("0::/user.slice/user-1000.slice/user@1000.service/user.slice/libpod-{ID}.scope\n")), Some(ID));
+        assert_eq!(container_of(&format!("0::/libpod_parent/{ID}\n")), Some(ID));
+        // Hybrid hierarchy, with the v1 controllers listed first
+        assert_eq!(container_of(&format!("12:cpu,cpuacct:/docker/{ID}\n1:name=systemd:/docker/{ID}\n0::/docker/{ID}\n")), Some(ID));
+    }
+
+    #[test]
+    fn not_a_container() {
+        assert_eq!(container_of("0::/user.slice/user-1000.slice/session-2.scope\n"), None);
+        assert_eq!(container_of("0::/system.slice/docker.service\n"), None);
+        assert_eq!(container_of("0::/\n"), None);
+        // cgroup v1 only
+        assert_eq!(unified_path("4:cpu,cpuacct:/user.slice\n1:name=systemd:/user.slice/session-2.scope\n"), None);
+    }
+
+    #[test]
+    fn cpu_stat() {
+        let stat = CpuStat::parse("usage_usec 1520000\nuser_usec 1200000\nsystem_usec 320000\nnr_periods 0\nnr_throttled 0\nthrottled_usec 0\n").unwrap();
+
+        assert_eq!(stat, CpuStat { usage_usec: 1520000, user_usec: 1200000, system_usec: 320000 });
+        assert_eq!(stat.usage_ns(), 1520000000);
+
+        let error = CpuStat::parse("user_usec 1200000\nsystem_usec 320000\n").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
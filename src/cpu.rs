@@ -0,0 +1,196 @@
+use std::{fmt, fs};
+
+use crate::util;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vendor {
+    Intel,
+    Amd,
+    Unknown
+}
+
+#[derive(Debug, Clone)]
+pub struct CpuInfo {
+    pub vendor: Vendor,
+
+    pub vendor_id: String,
+
+    // DisplayFamily, with the extended family folded in
+    pub family: u32,
+
+    // DisplayModel, with the extended model folded in
+    pub model: u32
+}
+
+impl CpuInfo {
+    /// Identifies the CPU with CPUID, falling back to /proc/cpuinfo when CPUID is unavailable.
+    pub fn detect() -> Result<CpuInfo, std::io::Error> {
+        if let Some(info) = CpuInfo::from_cpuid() {
+            return Ok(info);
+        }
+
+        CpuInfo::from_proc_cpuinfo()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn from_cpuid() -> Option<CpuInfo> {
+        use std::arch::x86_64::__cpuid;
+
+        #[allow(unused_unsafe)]
+        let (leaf0, leaf1) = unsafe { (__cpuid(0), __cpuid(1)) };
+
+        if leaf0.eax < 1 {
+            return None;
+        }
+
+        let mut vendor_bytes = Vec::<u8>::with_capacity(12);
+        vendor_bytes.extend_from_slice(&leaf0.ebx.to_le_bytes());
+        vendor_bytes.extend_from_slice(&leaf0.edx.to_le_bytes());
+        vendor_bytes.extend_from_slice(&leaf0.ecx.to_le_bytes());
+        let vendor_id = String::from_utf8_lossy(&vendor_bytes).into_owned();
+
+        let base_family = (leaf1.eax >> 8) & 0xF;
+        let base_model = (leaf1.eax >> 4) & 0xF;
+        let ext_family = (leaf1.eax >> 20) & 0xFF;
+        let ext_model = (leaf1.eax >> 16) & 0xF;
+
+        let family = if base_family == 0xF { base_family + ext_family } else { base_family };
+        let model = if base_family == 0x6 || base_family == 0xF { (ext_model << 4) + base_model } else { base_model };
+
+        Some(CpuInfo {
+            vendor: Vendor::from_vendor_id(&vendor_id),
+            vendor_id,
+            family,
+            model
+        })
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn from_cpuid() -> Option<CpuInfo> {
+        None
+    }
+
+    fn from_proc_cpuinfo() -> Result<CpuInfo, std::io::Error> {
+        CpuInfo::parse_cpuinfo(&fs::read_to_string("/proc/cpuinfo")?)
+    }
+
+    // The signature of the first processor in the contents of /proc/cpuinfo
+    fn parse_cpuinfo(cpuinfo: &str) -> Result<CpuInfo, std::io::Error> {
+        let mut vendor_id: Option<String> = None;
+        let mut family: Option<u32> = None;
+        let mut model: Option<u32> = None;
+
+        // Only the first processor block matters, all cores report the same signature.
+        for line in cpuinfo.lines().take_while(|l| !l.trim().is_empty()) {
+            let Some((key, value)) = line.split_once(':') else { continue };
+            let value = value.trim();
+
+            match key.trim() {
+                "vendor_id" => vendor_id = Some(value.to_owned()),
+                "cpu family" => family = value.parse::<u32>().ok(),
+                "model" => model = value.parse::<u32>().ok(),
+                _ => { }
+            }
+        }
+
+        match (vendor_id, family, model) {
+            (Some(vendor_id), Some(family), Some(model)) => Ok(CpuInfo {
+                vendor: Vendor::from_vendor_id(&vendor_id),
+                vendor_id,
+                family,
+                model
+            }),
+            _ => Err(util::invalid_data("/proc/cpuinfo has no CPU signature"))
+        }
+    }
+}
+
+impl Vendor {
+    fn from_vendor_id(vendor_id: &str) -> Vendor {
+        match vendor_id {
+            "GenuineIntel" => Vendor::Intel,
+            "AuthenticAMD" | "HygonGenuine" => Vendor::Amd,
+            _ => Vendor::Unknown
+        }
+    }
+}
+
+impl fmt::Display for CpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} family {:#04x} model {:#04x}", self.vendor_id, self.family, self.model)
+    }
+}
+
+/// Cumulative busy time of every logical CPU from `/proc/stat`, in nanoseconds, indexed by CPU number. Busy is
+/// everything but idle and iowait, at clock tick resolution. Offline CPUs read 0.
+pub fn busy_times() -> Result<Vec<u64>, std::io::Error> {
+    let stat = fs::read_to_string("/proc/stat")?;
+    let ticks_per_second = util::clock_ticks_per_second();
+    let mut busy = Vec::<u64>::new();
+
+    for line in stat.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(cpu) = fields.next().and_then(|f| f.strip_prefix("cpu")).and_then(|n| n.parse::<usize>().ok()) else { continue };
+
+        // user, nice, system, idle, iowait, irq, softirq, steal; guest time is already in user and nice
+        let ticks: Vec<u64> = fields.take(8).filter_map(|f| f.parse::<u64>().ok()).collect();
+        let busy_ticks: u64 = ticks.iter().enumerate().filter(|(i, _)| *i != 3 && *i != 4).map(|(_, t)| t).sum();
+
+        if busy.len() <= cpu {
+            busy.resize(cpu + 1, 0);
+        }
+        busy[cpu] = busy_ticks * 1000000000 / ticks_per_second;
+    }
+
+    Ok(busy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTEL: &str = "processor\t: 0
+vendor_id\t: GenuineIntel
+cpu family\t: 6
+model\t\t: 183
+model name\t: 13th Gen Intel(R) Core(TM) i9-13900K
+stepping\t: 1
+
+processor\t: 1
+vendor_id\t: GenuineIntel
+cpu family\t: 6
+model\t\t: 151
+";
+
+    const AMD: &str = "processor\t: 0
+vendor_id\t: AuthenticAMD
+cpu family\t: 25
+model\t\t: 97
+model name\t: AMD Ryzen 9 7950X 16-Core Processor
+";
+
+    #[test]
+    fn intel_signature_from_the_first_processor() {
+        let cpu = CpuInfo::parse_cpuinfo(INTEL).unwrap();
+
+        assert_eq!(cpu.vendor, Vendor::Intel);
+        assert_eq!((cpu.family, cpu.model), (6, 0xB7));
+        assert_eq!(cpu.to_string(), "GenuineIntel family 0x06 model 0xb7");
+    }
+
+    #[test]
+    fn amd_signature() {
+        let cpu = CpuInfo::parse_cpuinfo(AMD).unwrap();
+
+        assert_eq!(cpu.vendor, Vendor::Amd);
+        assert_eq!((cpu.family, cpu.model), (0x19, 0x61));
+    }
+
+    #[test]
+    fn missing_signature() {
+        let error = CpuInfo::parse_cpuinfo("processor\t: 0\nvendor_id\t: GenuineIntel\nmodel name\t: Something\n").unwrap_err();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(CpuInfo::parse_cpuinfo("").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::sample::CollectedPoint;
+use crate::source::Domain;
+
+/// Writes one row per sample to a CSV file, or a TSV file when the path ends in `.tsv`, for plotting.
+#[derive(Debug)]
+pub struct SampleExport {
+    writer: BufWriter<File>,
+
+    delimiter: char,
+
+    other_domains: Vec<Domain>,
+
+    // The header is written with the first row, which tells how many sockets and core counters there are
+    header_written: bool
+}
+
+/// What the post-processing derived from a pair of samples, next to the raw deltas.
+#[derive(Debug, Clone, Copy)]
+pub struct Derived {
+    // CPU time of the target between the two samples, in nanoseconds. `None` when schedstat could not be read.
+    pub target_cpu_time: Option<u64>,
+
+    // Energy attributed to the target, in Joules
+    pub attributed: Option<f64>,
+
+    // Whether the sample is inside the measured window
+    pub in_window: bool
+}
+
+impl SampleExport {
+    pub fn create(path: impl AsRef<Path>, other_domains: &[Domain]) -> Result<SampleExport, std::io::Error> {
+        let path = path.as_ref();
+
+        Ok(SampleExport {
+            writer: BufWriter::new(File::create(path)?),
+            delimiter: if path.extension().is_some_and(|e| e == "tsv") { '\t' } else { ',' },
+            other_domains: other_domains.to_vec(),
+            header_written: false
+        })
+    }
+
+    // There is a column per socket, per core counter and per extra domain.
+    fn write_header(&mut self, sockets: usize, cores: usize) -> Result<(), std::io::Error> {
+        let other_domains = &self.other_domains;
+
+        let mut columns: Vec<String> = ["time_s", "interval_s", "in_window", "package_j"].iter().map(|c| c.to_string()).collect();
+        columns.extend((0..sockets).map(|s| format!("socket{s}_j")));
+        columns.push("cpu_j".to_owned());
+        columns.extend((0..cores).map(|c| format!("core{c}_j")));
+        columns.extend(other_domains.iter().map(|d| format!("{d}_j")));
+        columns.extend(["target_cpu_ns", "package_w", "cpu_w"].iter().map(|c| c.to_string()));
+        columns.extend(other_domains.iter().map(|d| format!("{d}_w")));
+        columns.extend(["attributed_j", "attributed_w"].iter().map(|c| c.to_string()));
+
+        writeln!(self.writer, "{}", columns.join(&self.delimiter.to_string()))
+    }
+
+    /// Writes the row of `point`, whose deltas were counted since `last`.
+    pub fn write(&mut self, last: &CollectedPoint, point: &CollectedPoint, derived: Derived) -> Result<(), std::io::Error> {
+        if !self.header_written {
+            self.write_header(point.sockets.len(), point.cores.len())?;
+            self.header_written = true;
+        }
+
+        let interval = (point.time - last.time) as f64 / 1000000000.0;
+        let watts = |energy: f64| if interval > 0.0 { (energy / interval).to_string() } else { String::new() };
+
+        let mut fields: Vec<String> = vec![
+            (point.time as f64 / 1000000000.0).to_string(),
+            interval.to_string(),
+            (derived.in_window as u8).to_string(),
+            point.package.to_string()
+        ];
+        fields.extend(point.sockets.iter().map(|e| e.to_string()));
+        fields.push(point.cpu_power.to_string());
+        fields.extend(point.cores.iter().map(|e| e.to_string()));
+        fields.extend(point.other.iter().map(|e| e.to_string()));
+        fields.push(derived.target_cpu_time.map(|t| t.to_string()).unwrap_or_default());
+        fields.push(watts(point.package));
+        fields.push(watts(point.cpu_power));
+        fields.extend(point.other.iter().map(|e| watts(*e)));
+        fields.push(derived.attributed.map(|e| e.to_string()).unwrap_or_default());
+        fields.push(derived.attributed.map(watts).unwrap_or_default());
+
+        writeln!(self.writer, "{}", fields.join(&self.delimiter.to_string()))
+    }
+
+    pub fn finish(mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn point(time: u64, sched: u64) -> CollectedPoint {
+        CollectedPoint {
+            time,
+            package: 10.0,
+            sockets: vec![6.0, 4.0],
+            cpu_power: 8.0,
+            cores: vec![5.0, 3.0],
+            other: vec![2.0, 1.0],
+            sched,
+            sched_cpus: Vec::new(),
+            busy_cpus: Vec::new()
+        }
+    }
+
+    // Exports a sample inside the window, and one without elapsed time or derived values, to a file named `name`
+    fn export(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("powermeter-test-{}-{name}", std::process::id()));
+        let mut export = SampleExport::create(&path, &[Domain::Dram, Domain::Psys]).unwrap();
+        let derived = Derived { target_cpu_time: Some(250_000_000), attributed: Some(4.0), in_window: true };
+
+        export.write(&point(0, 0), &point(500_000_000, 250_000_000), derived).unwrap();
+        export.write(&point(500_000_000, 250_000_000), &point(500_000_000, 250_000_000), Derived {
+            target_cpu_time: None,
+            attributed: None,
+            in_window: false
+        }).unwrap();
+        export.finish().unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        content
+    }
+
+    #[test]
+    fn csv_columns() {
+        let content = export("export.csv");
+        let lines: Vec<&str> = content.lines().collect();
+
+        assert_eq!(lines, [
+            "time_s,interval_s,in_window,package_j,socket0_j,socket1_j,cpu_j,core0_j,core1_j,dram_j,psys_j,\
+             target_cpu_ns,package_w,cpu_w,dram_w,psys_w,attributed_j,attributed_w",
+            "0.5,0.5,1,10,6,4,8,5,3,2,1,250000000,20,16,4,2,4,8",
+            // No time passed, so there is no power, and nothing was derived
+            "0.5,0,0,10,6,4,8,5,3,2,1,,,,,,,"
+        ]);
+    }
+
+    #[test]
+    fn tsv_columns() {
+        let content = export("export.tsv");
+        let lines: Vec<&str> = content.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("time_s\tinterval_s\tin_window\tpackage_j\tsocket0_j\t"));
+        assert_eq!(lines[1], "0.5\t0.5\t1\t10\t6\t4\t8\t5\t3\t2\t1\t250000000\t20\t16\t4\t2\t4\t8");
+        assert!(lines.iter().all(|l| l.split('\t').count() == 18));
+    }
+}
@@ -0,0 +1,203 @@
+use crate::cpu::CpuInfo;
+use crate::msr::{MsrCores, RAPL_COUNTER_WIDTH};
+use crate::source::{Domain, EnergySource, Units};
+use crate::topology::Topology;
+
+const MSR_RAPL_POWER_UNIT:      u64 = 0x606;
+const MSR_PP0_ENERGY_STATUS:    u64 = 0x639;
+const MSR_PKG_ENERGY_STATUS:    u64 = 0x611;
+const MSR_DRAM_ENERGY_STATUS:   u64 = 0x619;
+const MSR_PP1_ENERGY_STATUS:    u64 = 0x641;
+const MSR_PLATFORM_ENERGY_COUNTER: u64 = 0x64D;
+const MSR_PKG_POWER_INFO:       u64 = 0x614;
+
+const THERMAL_SPEC_POWER_MASK:  u64 = 0x7FFF;
+const MAX_POWER_MASK:           u64 = 0x7FFF_0000_0000;
+
+// Servers whose DRAM domain ignores MSR_RAPL_POWER_UNIT and always counts in 2^-16 J (15.3 uJ)
+const DRAM_FIXED_ENERGY_UNIT: f64 = 1.0 / 65536.0;
+
+// Family 6 DisplayModel values with both MSR_PKG_ENERGY_STATUS and MSR_PP0_ENERGY_STATUS
+const SUPPORTED_MODELS: &[u32] = &[
+    // Atom: Silvermont, Airmont, Goldmont, Goldmont Plus, Tremont, Gracemont
+    0x37, 0x4A, 0x4C, 0x5A, 0x5D, 0x5C, 0x5F, 0x7A, 0x86, 0x96, 0x9C, 0xBE,
+    // Sandy Bridge, Ivy Bridge
+    0x2A, 0x2D, 0x3A, 0x3E,
+    // Haswell, Broadwell client
+    0x3C, 0x45, 0x46, 0x3D, 0x47,
+    // Skylake, Kaby Lake, Coffee Lake, Cannon Lake, Comet Lake
+    0x4E, 0x5E, 0x8E, 0x9E, 0x66, 0xA5, 0xA6,
+    // Ice Lake client, Tiger Lake, Rocket Lake
+    0x7D, 0x7E, 0x8C, 0x8D, 0xA7,
+    // Alder Lake, Raptor Lake, Meteor Lake, Lunar Lake, Arrow Lake
+    0x97, 0x9A, 0xB7, 0xBA, 0xBF, 0xAA, 0xAC, 0xBD, 0xC5, 0xC6
+];
+
+// Servers with package energy but no PP0 counter: Haswell-EP, Broadwell-EP/DE, Skylake/Cascade Lake/Cooper Lake-SP,
+// Ice Lake-SP/D, Sapphire, Emerald and Granite Rapids, Xeon Phi (Knights Landing, Knights Mill)
+const PACKAGE_ONLY_MODELS: &[u32] = &[0x3F, 0x4F, 0x56, 0x55, 0x6A, 0x6C, 0x8F, 0xCF, 0xAD, 0xAE, 0x57, 0x85];
+
+// Client parts with a PP1 (uncore / integrated GPU) counter
+const PP1_MODELS: &[u32] = &[
+    0x5C, 0x7A, 0x96, 0x9C, 0xBE,
+    0x2A, 0x3A, 0x3C, 0x45, 0x46, 0x3D, 0x47,
+    0x4E, 0x5E, 0x8E, 0x9E, 0x66, 0xA5, 0xA6,
+    0x7D, 0x7E, 0x8C, 0x8D, 0xA7,
+    0x97, 0x9A, 0xB7, 0xBA, 0xBF, 0xAA, 0xAC, 0xBD, 0xC5, 0xC6
+];
+
+// Parts with a DRAM counter: every server since Sandy Bridge-EP, and client parts since Haswell
+const DRAM_MODELS: &[u32] = &[
+    0x5C, 0x5F, 0x7A, 0x86,
+    0x2D, 0x3E, 0x3F, 0x4F, 0x56, 0x55, 0x6A, 0x6C, 0x8F, 0xCF, 0xAD, 0xAE, 0x57, 0x85,
+    0x3C, 0x45, 0x46, 0x3D, 0x47,
+    0x4E, 0x5E, 0x8E, 0x9E, 0x66, 0xA5, 0xA6,
+    0x7D, 0x7E, 0x8C, 0x8D, 0xA7,
+    0x97, 0x9A, 0xB7, 0xBA, 0xBF, 0xAA, 0xAC, 0xBD, 0xC5, 0xC6
+];
+
+// DRAM energy counts in DRAM_FIXED_ENERGY_UNIT: Haswell-EP, Broadwell-EP/DE, Skylake-SP, Ice Lake-SP/D, Sapphire, Emerald and Granite Rapids, Xeon Phi
+const DRAM_FIXED_UNIT_MODELS: &[u32] = &[0x3F, 0x4F, 0x56, 0x55, 0x6A, 0x6C, 0x8F, 0xCF, 0xAD, 0xAE, 0x57, 0x85];
+
+// Parts with MSR_PLATFORM_ENERGY_COUNTER: Skylake client and later, Sapphire Rapids and later
+const PSYS_MODELS: &[u32] = &[
+    0x4E, 0x5E, 0x8E, 0x9E, 0x66, 0xA5, 0xA6,
+    0x7D, 0x7E, 0x8C, 0x8D, 0xA7,
+    0x97, 0x9A, 0xB7, 0xBA, 0xBF, 0xAA, 0xAC, 0xBD, 0xC5, 0xC6,
+    0x8F, 0xCF, 0xAD, 0xAE
+];
+
+#[derive(Debug)]
+pub struct IntelCpuContext {
+    // One CPU of every socket. All Intel RAPL counters, PP0 included, are package-scoped.
+    packages: MsrCores,
+
+    topology: Topology,
+
+    // Package is always present, Core unless the model is package-only, the rest depends on the model
+    domains: Vec<Domain>,
+
+    dram_energy_unit: f64,
+
+    // From MSR_PKG_POWER_INFO, in Watts
+    max_package_power: Option<f64>
+}
+
+/// Returns `None` if the CPU has the RAPL MSRs we need, or the reason why it cannot be measured.
+pub fn unsupported_reason(cpu: &CpuInfo) -> Option<String> {
+    if cpu.family != 6 {
+        Some(format!("Intel family {:#04x} has no RAPL energy counters.", cpu.family))
+    } else if !SUPPORTED_MODELS.contains(&cpu.model) && !PACKAGE_ONLY_MODELS.contains(&cpu.model) {
+        Some(format!("Intel model {:#04x} is not known to support RAPL energy counters.", cpu.model))
+    } else {
+        None
+    }
+}
+
+fn domain_msr(domain: Domain) -> u64 {
+    match domain {
+        Domain::Package => MSR_PKG_ENERGY_STATUS,
+        Domain::Core => MSR_PP0_ENERGY_STATUS,
+        Domain::Uncore => MSR_PP1_ENERGY_STATUS,
+        Domain::Dram => MSR_DRAM_ENERGY_STATUS,
+        Domain::Psys => MSR_PLATFORM_ENERGY_COUNTER
+    }
+}
+
+impl IntelCpuContext {
+    /// Opens the MSRs of one CPU of every socket. The optional domains are taken from the model tables of `cpu`,
+    /// and only kept if their counter can be read and has started counting.
+    pub fn new(cpu: &CpuInfo) -> Result<IntelCpuContext, std::io::Error> {
+        let topology = Topology::detect()?;
+        let packages = MsrCores::open(&topology.package_cpus(), MSR_RAPL_POWER_UNIT)?;
+
+        let mut domains = vec![Domain::Package];
+        if !PACKAGE_ONLY_MODELS.contains(&cpu.model) && packages.read_u64(0, MSR_PP0_ENERGY_STATUS).is_ok_and(|raw| raw != 0) {
+            domains.push(Domain::Core);
+        }
+        for (domain, models) in [(Domain::Uncore, PP1_MODELS), (Domain::Dram, DRAM_MODELS), (Domain::Psys, PSYS_MODELS)] {
+            if models.contains(&cpu.model) && packages.read_u64(0, domain_msr(domain)).is_ok_and(|raw| raw != 0) {
+                domains.push(domain);
+            }
+        }
+
+        let dram_energy_unit = if DRAM_FIXED_UNIT_MODELS.contains(&cpu.model) {
+            DRAM_FIXED_ENERGY_UNIT
+        } else {
+            packages.units(0).energy
+        };
+
+        // The maximum power field is often left at zero, the thermal design power is the next best bound.
+        let max_package_power = packages.read_u64(0, MSR_PKG_POWER_INFO).ok().and_then(|info| {
+            let max_power = (info & MAX_POWER_MASK) >> 32;
+            let tdp = info & THERMAL_SPEC_POWER_MASK;
+            let raw = if max_power != 0 { max_power } else { tdp };
+
+            (raw != 0).then(|| raw as f64 * packages.units(0).power)
+        });
+
+        Ok(IntelCpuContext {
+            packages,
+            topology,
+            domains,
+            dram_energy_unit,
+            max_package_power
+        })
+    }
+}
+
+impl EnergySource for IntelCpuContext {
+    fn name(&self) -> &'static str { "intel-msr" }
+
+    fn domains(&self) -> Vec<Domain> { self.domains.clone() }
+
+    // PP0 counts all cores of a package together, so there is one core counter per socket, and none without PP0.
+    fn get_cores(&self) -> usize {
+        if self.domains.contains(&Domain::Core) { self.packages.get_cores() } else { 0 }
+    }
+
+    fn get_packages(&self) -> usize { self.packages.get_cores() }
+
+    fn read_raw_per_package(&self, domain: Domain) -> Result<Vec<u64>, std::io::Error> {
+        if !self.domains.contains(&domain) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("No {domain} energy counter")));
+        }
+
+        // PSys counts the whole platform, so every package would read the same counter
+        match domain {
+            Domain::Psys => Ok(vec![self.packages.read_counter(0, domain_msr(domain))?]),
+            _ => self.packages.read_counters(domain_msr(domain))
+        }
+    }
+
+    fn all_core_raw(&self) -> Result<Vec<u64>, std::io::Error> {
+        if !self.domains.contains(&Domain::Core) {
+            return Ok(Vec::new());
+        }
+
+        self.packages.read_counters(MSR_PP0_ENERGY_STATUS)
+    }
+
+    fn core_of_cpu(&self, cpu: usize) -> Option<usize> {
+        self.topology.package_of(cpu)
+    }
+
+    fn counter_width(&self, _domain: Domain) -> u32 { RAPL_COUNTER_WIDTH }
+
+    fn units(&self) -> Units { self.packages.units(0) }
+
+    fn energy_unit(&self, domain: Domain) -> f64 {
+        match domain {
+            Domain::Dram => self.dram_energy_unit,
+            _ => self.packages.units(0).energy
+        }
+    }
+
+    // PP0 never draws more than the package it is part of.
+    fn max_power(&self, domain: Domain) -> Option<f64> {
+        match domain {
+            Domain::Package | Domain::Core => self.max_package_power,
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,30 @@
+//! Read CPU energy counters on Intel and AMD processors.
+//!
+//! Use [`cpu::CpuInfo::detect`] and [`source::open`] to get an [`EnergySource`] for the running CPU,
+//! or construct [`AmdCpuContext`], [`IntelCpuContext`], [`PerfContext`] or [`PowercapContext`] directly.
+
+pub mod amd;
+pub mod attribution;
+pub mod baseline;
+pub mod cgroup;
+pub mod cpu;
+pub mod export;
+pub mod intel;
+pub mod msr;
+pub mod perf;
+pub mod powercap;
+pub mod process;
+pub mod report;
+pub mod sample;
+pub mod source;
+pub mod stats;
+pub mod summary;
+pub mod topology;
+pub mod trace;
+mod util;
+
+pub use amd::AmdCpuContext;
+pub use intel::IntelCpuContext;
+pub use perf::PerfContext;
+pub use powercap::PowercapContext;
+pub use source::{Backend, Domain, EnergySource, Units};
@@ -0,0 +1,1010 @@
+extern crate num_cpus;
+use clap::{Parser, Subcommand};
+use powermeter::cpu::{self, CpuInfo};
+use powermeter::export::SampleExport;
+use powermeter::cgroup::Cgroup;
+use powermeter::process::{self, Accounting, CpuPlacement, CpuTimeSource, CpuTimeTracker, Target, TargetState};
+use powermeter::report::{self, CompareReport, Format, Host, Report, RunsReport};
+use powermeter::sample::{CollectedPoint, SampleStore};
+use powermeter::source::{self, Backend, Domain, EnergySource};
+use powermeter::attribution::{Machine, Model};
+use powermeter::baseline::Baseline;
+use powermeter::summary::{self, Summary, SummaryOptions, TrimMode};
+use powermeter::topology::Topology;
+use powermeter::trace::{CounterInfo, RawSample, Trace, TraceHeader, TraceWriter, TRACE_VERSION};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::process::CommandExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Mode>,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand)]
+enum Mode {
+    /// Measure a program and save the raw counter readings to a trace file
+    Record {
+        #[arg(short, long, help="Trace file to write")]
+        output: PathBuf,
+
+        #[command(flatten)]
+        run: RunArgs,
+    },
+
+    /// Recompute the summary of a recorded trace
+    Analyze {
+        trace: PathBuf,
+
+        #[command(flatten)]
+        summary: SummaryArgs,
+    },
+
+    /// Run two or more programs in interleaved rounds and compare each with the first
+    Compare {
+        #[arg(required = true, num_args = 2.., help="Programs to compare, the first being the baseline")]
+        programs: Vec<String>,
+
+        #[arg(short = 'n', long, help="Times every program is run, at least 2", default_value="5")]
+        rounds: usize,
+
+        #[arg(long, help="Rounds to discard before the measured --rounds", default_value="0")]
+        warmup: usize,
+
+        #[command(flatten)]
+        measure: MeasureArgs,
+    },
+}
+
+// How the samples are summarized, shared by measuring and analyzing a trace
+#[derive(clap::Args, Clone)]
+struct SummaryArgs {
+    #[arg(short, long, help="Portion of startup time to ignore.", default_value="0.2")]
+    startup_ignore: f64,
+
+    #[arg(short, long, help="Portion of terminating time to ignore.", default_value="0.2")]
+    end_ignore: f64,
+
+    #[arg(long, value_name = "SECONDS", conflicts_with_all = ["startup_ignore", "end_ignore"], help="Seconds of startup time to ignore, instead of a portion")]
+    skip_start: Option<f64>,
+
+    #[arg(long, value_name = "SECONDS", conflicts_with_all = ["startup_ignore", "end_ignore"], help="Seconds of terminating time to ignore, instead of a portion")]
+    skip_end: Option<f64>,
+
+    #[arg(long, conflicts_with_all = ["startup_ignore", "end_ignore", "skip_start", "skip_end"], help="Summarize every sample, ignoring nothing")]
+    no_trim: bool,
+
+    #[arg(long, conflicts_with_all = ["startup_ignore", "end_ignore", "skip_start", "skip_end", "no_trim"], help="Summarize the longest stretch of steady package power, found by change-point detection")]
+    steady_state: bool,
+
+    #[arg(short, long, value_enum, help="Format of the summary", default_value="text")]
+    format: Format,
+
+    #[arg(short = 'x', long, help="Write every sample to a CSV file, or TSV if the name ends in .tsv")]
+    export: Option<PathBuf>,
+
+    #[arg(long, help="Report the energy of every core counter")]
+    per_core: bool,
+
+    #[arg(short, long, value_enum, help="How energy is attributed to the target", default_value="cpu-time")]
+    model: Model,
+
+    #[arg(long, value_name = "WATTS", help="Idle power of the CPU, subtracted by --model dynamic")]
+    idle_power: Option<f64>,
+}
+
+#[derive(clap::Args, Clone)]
+struct RunArgs {
+    program: Option<String>,
+
+    #[arg(long, conflicts_with = "program", help="Measure a running process and its descendants instead of starting a program")]
+    pid: Option<u32>,
+
+    #[arg(short = 'a', long, conflicts_with_all = ["program", "pid"], help="Measure the whole system, without a target to attribute energy to")]
+    system_wide: bool,
+
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["program", "pid", "system_wide", "container", "unit"], help="Measure every process in a cgroup v2, given as a directory or relative to the cgroup mount point")]
+    cgroup: Option<PathBuf>,
+
+    #[arg(long, value_name = "ID", conflicts_with_all = ["program", "pid", "system_wide", "unit"], help="Measure a Docker, Podman, containerd or CRI-O container, by ID or a unique prefix of it")]
+    container: Option<String>,
+
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["program", "pid", "system_wide"], help="Measure a systemd unit, a service unless the name has a suffix")]
+    unit: Option<String>,
+
+    #[arg(long, value_parser = parse_seconds, help="Stop measuring after this many seconds, with --pid, --system-wide or a cgroup")]
+    duration: Option<Duration>,
+
+    #[arg(long, help="Run the program this many times and report statistics over the runs", default_value="1")]
+    runs: usize,
+
+    #[arg(long, help="Runs of the program to discard before the measured --runs", default_value="0")]
+    warmup: usize,
+
+    #[command(flatten)]
+    measure: MeasureArgs,
+}
+
+// How the target is measured, shared by everything that runs one
+#[derive(clap::Args, Clone)]
+struct MeasureArgs {
+    #[arg(long, value_enum, help="How to measure the CPU time of the target", default_value="proc")]
+    accounting: Accounting,
+
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, help="Measure the idle power for this long before the target starts, and report energy above it")]
+    calibrate: Option<Duration>,
+
+    #[arg(long, help="Also measure the idle power for the --calibrate length after the target exits")]
+    calibrate_after: bool,
+
+    #[command(flatten)]
+    summary: SummaryArgs,
+
+    #[arg(short, long, help="Sampling interval (ms) [default: 50, or shorter if the counters can wrap sooner]")]
+    period: Option<u32>,
+
+    #[arg(short, long, help="Record to disk instead of memory", default_value="false")]
+    disk: bool,
+
+    #[arg(long, help="Trace file for --disk [default: powermeter-<PID>.samples in the temp directory]")]
+    disk_path: Option<PathBuf>,
+
+    #[arg(short, long, value_enum, help="Where to read energy counters from", default_value="auto")]
+    backend: Backend,
+}
+
+// Set by SIGINT, stops measuring a target we did not start, or the whole system
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop_on_sigint(_: libc::c_int) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+// Set when stdout carries a machine-readable report, progress messages then go to stderr
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+fn parse_seconds(arg: &str) -> Result<Duration, String> {
+    let secs = arg.parse::<f64>().map_err(|e| e.to_string())?;
+
+    Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+}
+
+// The energy counters and what is known about them, shared by all runs
+struct Meter {
+    ctx: Box<dyn EnergySource>,
+
+    period: Duration,
+
+    has_core: bool,
+
+    other_domains: Vec<Domain>,
+
+    machine: Machine,
+
+    package_wrap: Option<Duration>,
+
+    core_wrap: Option<Duration>,
+
+    host: Host
+}
+
+// Sampling period when the user does not pick one and the counters are slow to wrap
+const DEFAULT_PERIOD: Duration = Duration::from_millis(50);
+
+fn main() {
+    let cli = Cli::parse();
+
+    match cli.command {
+        None => run(cli.run, None),
+        Some(Mode::Record { output, run: args }) => run(args, Some(output)),
+        Some(Mode::Analyze { trace, summary }) => analyze(trace, summary),
+        Some(Mode::Compare { programs, rounds, warmup, measure }) => compare(programs, rounds, warmup, measure)
+    }
+}
+
+// Measures the program, saving the raw readings to `trace_path` if given.
+fn run(args: RunArgs, trace_path: Option<PathBuf>) {
+    let measuring_cgroup = args.cgroup.is_some() || args.container.is_some() || args.unit.is_some();
+    if args.program.is_none() && args.pid.is_none() && !args.system_wide && !measuring_cgroup {
+        eprintln!("Specify the program you want to run, a --pid to attach to, a --cgroup, --container or --unit, or --system-wide.");
+        std::process::exit(1);
+    }
+    check_measure_args(&args.measure);
+    if args.duration.is_some() && args.program.is_some() {
+        eprintln!("--duration can only be used with --pid, --system-wide or a cgroup.");
+        std::process::exit(1);
+    }
+    let repeated = args.runs != 1 || args.warmup != 0;
+    if repeated && (args.program.is_none() || trace_path.is_some() || args.measure.summary.export.is_some()) {
+        eprintln!("--runs and --warmup need a program, and cannot be used with record or --export.");
+        std::process::exit(1);
+    }
+    if args.runs == 0 {
+        eprintln!("--runs must be at least 1.");
+        std::process::exit(1);
+    }
+    STATUS_TO_STDERR.store(args.measure.summary.format != Format::Text, Ordering::Relaxed);
+
+    let meter = open_meter(&args.measure);
+
+    if !repeated {
+        let Some(report) = measure(&args, &meter, trace_path.as_deref()) else {
+            std::process::exit(1);
+        };
+        print_report(args.measure.summary.format, &report);
+        return;
+    }
+
+    let mut reports = Vec::<Report>::with_capacity(args.runs);
+    for run in 0..args.warmup + args.runs {
+        if run < args.warmup {
+            status!("Warmup run {}/{}", run + 1, args.warmup);
+            measure(&args, &meter, None);
+            continue;
+        }
+
+        status!("Run {}/{}", run - args.warmup + 1, args.runs);
+        let Some(report) = measure(&args, &meter, None) else {
+            status!("Run {}/{} is left out", run - args.warmup + 1, args.runs);
+            continue;
+        };
+        status!("Run {}/{}: {} s, {} J package, {} J CPU", run - args.warmup + 1, args.runs, report.duration_ns as f64 / 1000000000.0, report.energy.package, report.energy.cpu);
+        reports.push(report);
+    }
+
+    if reports.is_empty() {
+        eprintln!("Every run was too short to summarize.");
+        std::process::exit(1);
+    }
+
+    let report = RunsReport::new(reports, args.warmup);
+    match args.measure.summary.format {
+        Format::Text => print!("{report}"),
+        Format::Json => println!("{}", report.to_json())
+    }
+}
+
+// Runs every program once per round, each round in a different order so drift in temperature or background load is
+// spread over all of them, and compares each with the first.
+fn compare(programs: Vec<String>, rounds: usize, warmup: usize, measure_args: MeasureArgs) {
+    if rounds < 2 {
+        eprintln!("--rounds must be at least 2 to test the differences.");
+        std::process::exit(1);
+    }
+    if measure_args.summary.export.is_some() {
+        eprintln!("--export cannot be used with compare.");
+        std::process::exit(1);
+    }
+    check_measure_args(&measure_args);
+    STATUS_TO_STDERR.store(measure_args.summary.format != Format::Text, Ordering::Relaxed);
+
+    let meter = open_meter(&measure_args);
+    let args: Vec<RunArgs> = programs.iter()
+        .map(|program| RunArgs {
+            program: Some(program.clone()),
+            pid: None,
+            system_wide: false,
+            cgroup: None,
+            container: None,
+            unit: None,
+            duration: None,
+            runs: 1,
+            warmup: 0,
+            measure: measure_args.clone()
+        })
+        .collect();
+
+    let mut reports: Vec<Vec<Report>> = programs.iter().map(|_| Vec::with_capacity(rounds)).collect();
+    for round in 0..warmup + rounds {
+        for i in 0..args.len() {
+            let program = (round + i) % args.len();
+
+            if round < warmup {
+                status!("Warmup round {}/{}, program {program}", round + 1, warmup);
+                measure(&args[program], &meter, None);
+                continue;
+            }
+
+            status!("Round {}/{}, program {program}", round - warmup + 1, rounds);
+            let Some(report) = measure(&args[program], &meter, None) else {
+                status!("Round {}/{}, program {program} is left out", round - warmup + 1, rounds);
+                continue;
+            };
+            status!("Round {}/{}, program {program}: {} s, {} J package, {} J CPU", round - warmup + 1, rounds, report.duration_ns as f64 / 1000000000.0, report.energy.package, report.energy.cpu);
+            reports[program].push(report);
+        }
+    }
+
+    if reports.iter().any(|r| r.len() < 2) {
+        eprintln!("Every program needs at least two rounds long enough to summarize to test the differences.");
+        std::process::exit(1);
+    }
+
+    let report = CompareReport::new(reports.into_iter().map(|r| RunsReport::new(r, warmup)).collect(), warmup);
+    match measure_args.summary.format {
+        Format::Text => print!("{report}"),
+        Format::Json => println!("{}", report.to_json())
+    }
+}
+
+// Identifies the CPU and opens the energy counters. Exits on errors.
+fn open_meter(args: &MeasureArgs) -> Meter {
+    let ncpus = num_cpus::get();
+
+    let cpu = match CpuInfo::detect() {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            eprintln!("Unable to identify the CPU.");
+            eprintln!("{:#?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    status!("Detected {cpu}");
+
+    let ctx = match source::open(&cpu, args.backend) {
+        Ok(ctx) => ctx,
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+            eprintln!("Unsupported CPU: {e}");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Unable to establish CPU context. Are you root?");
+            eprintln!("{:#?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let safe_period = ctx.max_safe_period();
+    let period = match (args.period, safe_period) {
+        (Some(ms), Some(safe)) if Duration::from_millis(ms.into()) > safe => {
+            eprintln!("A sampling interval of {ms} ms risks missing counter wraparounds, the longest safe interval is {} ms.", safe.as_millis());
+            std::process::exit(1);
+        }
+        (Some(ms), _) => Duration::from_millis(ms.into()),
+        (None, Some(safe)) => DEFAULT_PERIOD.min(safe),
+        (None, None) => DEFAULT_PERIOD
+    };
+
+    status!("Reading {} counters, {} sockets, {} core counters, sampling interval is {} ms", ctx.name(), ctx.get_packages(), ctx.get_cores(), period.as_millis());
+    match safe_period {
+        Some(safe) => status!("Longest safe sampling interval is {} ms", safe.as_millis()),
+        None => status!("Maximum power is unknown, the sampling interval cannot be checked against counter wraparound")
+    }
+
+    let has_core = ctx.domains().contains(&Domain::Core);
+    if !has_core {
+        status!("No core energy counter available, attributing package energy instead.");
+    }
+
+    let other_domains: Vec<Domain> = ctx.domains().into_iter().filter(|d| *d != Domain::Package && *d != Domain::Core).collect();
+    if !other_domains.is_empty() {
+        status!("Also reading {} energy", other_domains.iter().map(|d| d.name()).collect::<Vec<_>>().join(", "));
+    }
+
+    // Which counter and physical core every logical CPU belongs to, for the models that look at where the target ran
+    let machine = match Topology::detect() {
+        Ok(topology) => {
+            let cpus = topology.packages().iter().flat_map(|p| p.cpus.iter()).max().map_or(0, |cpu| cpu + 1);
+            Machine {
+                ncpus,
+                counter_of_cpu: (0..cpus).map(|cpu| if has_core { ctx.core_of_cpu(cpu) } else { topology.package_of(cpu) }).collect(),
+                core_of_cpu: (0..cpus).map(|cpu| topology.core_of(cpu)).collect()
+            }
+        }
+        Err(_) => Machine { ncpus, ..Default::default() }
+    };
+
+    let package_wrap = ctx.wrap_interval(Domain::Package);
+    let core_wrap = ctx.wrap_interval(Domain::Core);
+    if let Some(wrap) = package_wrap {
+        status!("Package counter wraps after {:.1} s at most at {} W", wrap.as_secs_f64(), ctx.max_power(Domain::Package).unwrap_or(0.0));
+    }
+
+    Meter {
+        host: Host::detect(&cpu, ctx.name()),
+        ctx,
+        period,
+        has_core,
+        other_domains,
+        machine,
+        package_wrap,
+        core_wrap
+    }
+}
+
+// Measures one run of the target, saving the raw readings to `trace_path` if given. None when the run was too short to
+// summarize.
+fn measure(args: &RunArgs, meter: &Meter, trace_path: Option<&Path>) -> Option<Report> {
+    let ctx = meter.ctx.as_ref();
+    let period = meter.period;
+    let has_core = meter.has_core;
+    let other_domains = &meter.other_domains;
+    let package_wrap = meter.package_wrap;
+    let core_wrap = meter.core_wrap;
+    let measuring_cgroup = args.cgroup.is_some() || args.container.is_some() || args.unit.is_some();
+
+    if let Some(program) = &args.program {
+        status!("{:?}", program);
+    }
+
+    let mut baselines = Vec::<Baseline>::new();
+    if let Some(window) = args.measure.calibrate {
+        baselines.push(calibrate(ctx, window, period, other_domains));
+    }
+
+    let mut results = if args.measure.disk {
+        let path = args.measure.disk_path.clone().unwrap_or_else(|| std::env::temp_dir().join(format!("powermeter-{}.samples", std::process::id())));
+        match SampleStore::disk(&path, other_domains) {
+            Ok(store) => {
+                status!("Recording samples to {}", path.display());
+                store
+            }
+            Err(e) => {
+                eprintln!("Unable to create {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+    } else {
+        SampleStore::memory()
+    };
+
+    // Keep the output of the target out of a machine-readable report
+    let target_stdout = match args.measure.summary.format {
+        Format::Text => Stdio::inherit(),
+        _ => Stdio::from(std::io::stderr().as_fd().try_clone_to_owned().expect("Failed to duplicate stderr"))
+    };
+
+    let measured_cgroup = match (&args.cgroup, &args.container, &args.unit) {
+        (Some(path), _, _) => Some(Cgroup::open(path)),
+        (_, Some(id), _) => Some(Cgroup::container(id)),
+        (_, _, Some(name)) => Some(Cgroup::unit(name)),
+        _ => None
+    }.map(|cgroup| cgroup.unwrap_or_else(|e| {
+        eprintln!("Unable to find the cgroup to measure: {e}");
+        std::process::exit(1);
+    }));
+
+    let cgroup = match (measured_cgroup, args.measure.accounting, &args.program) {
+        (Some(cgroup), _, _) => {
+            status!("Measuring cgroup {}", cgroup.path().display());
+            Some(cgroup)
+        }
+        (None, Accounting::Cgroup, Some(_)) => match Cgroup::create(&format!("powermeter-{}", std::process::id())) {
+            Ok(cgroup) => {
+                status!("Running the target in cgroup {}", cgroup.path().display());
+                Some(cgroup)
+            }
+            Err(e) => {
+                status!("Unable to create a cgroup for the target, falling back to /proc accounting: {e}");
+                None
+            }
+        },
+        (None, Accounting::Cgroup, None) if !args.system_wide => {
+            status!("cgroup accounting needs a program started by powermeter, falling back to /proc accounting");
+            None
+        }
+        _ => None
+    };
+
+    let mut target = match (&args.program, args.pid) {
+        _ if args.system_wide || measuring_cgroup => None,
+        (Some(program), None) => {
+            let mut command = Command::new("sh");
+            command.arg("-c")
+                .arg("exec ".to_owned() + program)
+                .stdout(target_stdout);
+
+            // Join the cgroup between fork and exec, so every process the target starts is in it from the beginning
+            let cgroup_procs = cgroup.as_ref().map(|cgroup| cgroup.procs_file().expect("Failed to open cgroup.procs"));
+            if let Some(procs) = &cgroup_procs {
+                let fd = procs.as_raw_fd();
+                unsafe {
+                    command.pre_exec(move || {
+                        if libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1) < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+            }
+
+            let target_spawn = command.spawn();
+            let target = Target::Spawned(target_spawn.expect("Failed to start process"));
+
+            status!("Started process {}", target.pid());
+            Some(target)
+        }
+        (_, Some(pid)) => match Target::attach(pid) {
+            Ok(target) => {
+                status!("Attached to process {pid}");
+                Some(target)
+            }
+            Err(e) => {
+                eprintln!("Unable to attach to process {pid}: {e}");
+                std::process::exit(1);
+            }
+        },
+        (None, None) => unreachable!()
+    };
+    let target_pid: Option<u32> = target.as_ref().map(|t| t.pid());
+    let command = match (&args.program, target_pid) {
+        (Some(program), _) => Some(program.clone()),
+        (None, Some(pid)) => Some(process::command_line(pid).unwrap_or_default()),
+        (None, None) => None
+    };
+
+    // A target we started gets SIGINT too and exits, anything else is stopped here
+    if !matches!(target, Some(Target::Spawned(_))) {
+        unsafe { libc::signal(libc::SIGINT, stop_on_sigint as *const () as libc::sighandler_t) };
+
+        match args.duration {
+            Some(duration) => status!("Measuring for {} s, press Ctrl-C to stop early", duration.as_secs_f64()),
+            None => status!("Measuring until Ctrl-C is pressed")
+        }
+    }
+
+    let mut cputime_source = match (cgroup, target_pid) {
+        (Some(cgroup), _) => Some(CpuTimeSource::Cgroup(cgroup)),
+        (None, Some(root)) => Some(CpuTimeSource::Proc { root, tracker: CpuTimeTracker::new() }),
+        (None, None) => None
+    };
+    let mut placement = CpuPlacement::new();
+    // Reading where the target ran and how busy the CPUs were costs a walk over its threads and /proc/stat every
+    // sample, so it is only done when the model uses them, or for a trace that may be analyzed with any model
+    let model = args.measure.summary.model;
+    let track_placement = trace_path.is_some() || model.needs_placement();
+    let track_busy = trace_path.is_some() || model.needs_busy_times();
+    let accounting = cputime_source.as_ref().map(|s| s.accounting());
+    let cgroup_path = match &cputime_source {
+        Some(CpuTimeSource::Cgroup(cgroup)) => Some(cgroup.path().to_path_buf()),
+        _ => None
+    };
+    let Ok(mut cpu_energy_last) = ctx.all_core_raw() else {
+        eprintln!("Read Error: Cannot read core energy.");
+        exit_removing_cgroup(cputime_source.take());
+    };
+    let Ok(mut package_energy_last) = ctx.read_raw_per_package(Domain::Package) else {
+        eprintln!("Read Error: Cannot read package energy.");
+        exit_removing_cgroup(cputime_source.take());
+    };
+    let mut other_energy_last = Vec::<Vec<u64>>::with_capacity(other_domains.len());
+    for domain in other_domains {
+        match ctx.read_raw_per_package(*domain) {
+            Ok(energy) => other_energy_last.push(energy),
+            Err(_) => {
+                eprintln!("Read Error: Cannot read {domain} energy.");
+                exit_removing_cgroup(cputime_source.take());
+            }
+        }
+    }
+    let start_time = Instant::now();
+    let start_timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    // Describes the run, for the trace and the report
+    let mut counters = vec![CounterInfo::of(ctx, Domain::Package)];
+    if has_core {
+        counters.push(CounterInfo::of(ctx, Domain::Core));
+    }
+    counters.extend(other_domains.iter().map(|d| CounterInfo::of(ctx, *d)));
+
+    let header = TraceHeader {
+        version: TRACE_VERSION,
+        start_time: start_timestamp,
+        host: meter.host.clone(),
+        command,
+        pid: target_pid,
+        system_wide: args.system_wide,
+        accounting,
+        cgroup: cgroup_path,
+        period_ms: period.as_millis() as u64,
+        units: ctx.units(),
+        counters,
+        other_domains: other_domains.clone(),
+        counter_of_cpu: meter.machine.counter_of_cpu.clone(),
+        core_of_cpu: meter.machine.core_of_cpu.clone()
+    };
+
+    let mut trace = trace_path.map(|path| {
+        // The readings taken before the loop, which the first sample is counted from
+        let baseline = RawSample {
+            time: 0,
+            sched: 0,
+            package: package_energy_last.clone(),
+            cores: cpu_energy_last.clone(),
+            other: other_energy_last.clone(),
+            sched_cpus: Vec::new(),
+            busy_cpus: Vec::new()
+        };
+
+        let created = TraceWriter::create(path, header.clone())
+            .and_then(|mut trace| trace.sample(baseline).map(|_| trace))
+            .and_then(|mut trace| baselines.iter().try_for_each(|b| trace.baseline(b)).map(|_| trace));
+
+        match created {
+            Ok(trace) => {
+                status!("Recording raw counters to {}", path.display());
+                trace
+            }
+            Err(e) => {
+                eprintln!("Unable to create {}: {e}", path.display());
+                exit_removing_cgroup(cputime_source.take());
+            }
+        }
+    });
+    let exit_status: Option<report::ExitStatus>;
+    let mut last_read = start_time;
+    let mut possible_multiple_wraps: usize = 0;
+
+    loop {
+        /*
+            CPU Time slicing
+         */
+
+        let cputime = cputime_source.as_mut().map(|s| s.read()).unwrap_or(0);
+        let (sched_cpus, busy_cpus) = match &cputime_source {
+            Some(source) => (
+                if track_placement { placement.update(&source.threads()).to_vec() } else { Vec::new() },
+                if track_busy { cpu::busy_times().unwrap_or_default() } else { Vec::new() }
+            ),
+            None => (Vec::new(), Vec::new())
+        };
+
+        /*
+            Power reading
+         */
+
+        let Ok(cpu_energy) = ctx.all_core_raw() else {
+            eprintln!("Read Error: Cannot read core energy.");
+            exit_removing_cgroup(cputime_source.take());
+        };
+
+        let core_energy_delta: Vec<f64> = cpu_energy.iter().zip(&cpu_energy_last).map(|(a, b)| ctx.energy_delta(Domain::Core, *b, *a)).collect();
+        let cpu_energy_delta_sum: f64 = core_energy_delta.iter().sum();
+
+        let Ok(pkg_energy) = ctx.read_raw_per_package(Domain::Package) else {
+            eprintln!("Read Error: Cannot read package energy.");
+            exit_removing_cgroup(cputime_source.take());
+        };
+
+        let socket_energy_delta: Vec<f64> = pkg_energy.iter().zip(&package_energy_last).map(|(a, b)| ctx.energy_delta(Domain::Package, *b, *a)).collect();
+        let pkg_energy_delta: f64 = socket_energy_delta.iter().sum();
+
+        let mut other_energy = Vec::<Vec<u64>>::with_capacity(other_domains.len());
+        for domain in other_domains {
+            match ctx.read_raw_per_package(*domain) {
+                Ok(energy) => other_energy.push(energy),
+                Err(_) => {
+                    eprintln!("Read Error: Cannot read {domain} energy.");
+                    exit_removing_cgroup(cputime_source.take());
+                }
+            }
+        }
+
+        let other_energy_delta: Vec<f64> = other_energy.iter().zip(&other_energy_last).zip(other_domains)
+            .map(|((a, b), d)| a.iter().zip(b).map(|(a, b)| ctx.energy_delta(*d, *b, *a)).sum())
+            .collect();
+
+        // A reading more than one wrap interval after the previous one cannot tell one wraparound from several.
+        let read_time = Instant::now();
+        let elapsed = read_time - last_read;
+        if package_wrap.is_some_and(|t| elapsed >= t) || (has_core && core_wrap.is_some_and(|t| elapsed >= t)) {
+            possible_multiple_wraps += 1;
+        }
+        last_read = read_time;
+
+        let data = CollectedPoint {
+            time : (read_time - start_time).as_nanos() as u64,
+            package : pkg_energy_delta,
+            sockets : socket_energy_delta,
+            cpu_power : if has_core { cpu_energy_delta_sum } else { pkg_energy_delta },
+            cores : core_energy_delta,
+            other : other_energy_delta,
+            sched : cputime,
+            sched_cpus : sched_cpus.clone(),
+            busy_cpus : busy_cpus.clone()
+        };
+
+        if let Err(e) = results.push(data) {
+            eprintln!("Unable to record sample: {e}");
+            exit_removing_cgroup(cputime_source.take());
+        }
+
+        if let Some(trace) = &mut trace {
+            let sample = RawSample {
+                time: (read_time - start_time).as_nanos() as u64,
+                sched: cputime,
+                package: pkg_energy.clone(),
+                cores: cpu_energy.clone(),
+                other: other_energy.clone(),
+                sched_cpus,
+                busy_cpus
+            };
+
+            if let Err(e) = trace.sample(sample) {
+                eprintln!("Unable to record sample: {e}");
+                exit_removing_cgroup(cputime_source.take());
+            }
+        }
+
+        if let Some(TargetState::Exited(status)) = target.as_mut().map(|t| t.poll()) {
+            match &status {
+                Some(status) => status!("Target process has exited with {status}"),
+                None => status!("Target process has exited")
+            }
+            exit_status = status;
+            break;
+        }
+
+        // Container runtimes and systemd remove the cgroup when the container or unit stops
+        if let (true, Some(CpuTimeSource::Cgroup(cgroup))) = (measuring_cgroup, &cputime_source) {
+            if !cgroup.exists() {
+                status!("The cgroup has been removed, stopping");
+                exit_status = None;
+                break;
+            }
+        }
+
+        if args.duration.is_some_and(|d| read_time - start_time >= d) {
+            status!("Measurement window has ended");
+            exit_status = None;
+            break;
+        }
+
+        if STOP.load(Ordering::Relaxed) {
+            status!("Interrupted, stopping");
+            exit_status = None;
+            break;
+        }
+
+        cpu_energy_last = cpu_energy;
+        package_energy_last = pkg_energy;
+        other_energy_last = other_energy;
+
+        thread::sleep(period);
+    }
+
+    if let Some(target) = &mut target {
+        target.wait();
+    }
+
+    if let Some(CpuTimeSource::Cgroup(cgroup)) = cputime_source {
+        let path = cgroup.path().to_path_buf();
+        if let Err(e) = cgroup.remove() {
+            status!("Unable to remove cgroup {}: {e}", path.display());
+        }
+    }
+
+    if let (Some(window), true) = (args.measure.calibrate, args.measure.calibrate_after) {
+        let after = calibrate(ctx, window, period, other_domains);
+        if let Some(Err(e)) = trace.as_mut().map(|trace| trace.baseline(&after)) {
+            eprintln!("Unable to record the idle baseline: {e}");
+            std::process::exit(1);
+        }
+        baselines.push(after);
+    }
+    let baseline = Baseline::combine(&baselines);
+
+    if let Some(trace) = trace {
+        if let Err(e) = trace.finish(exit_status.clone()) {
+            eprintln!("Unable to finish trace: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    status!("Post-processing...");
+
+    let len = results.len();
+    let idle_power = args.measure.summary.idle_power.or(baseline.as_ref().map(|b| b.power.cpu));
+    let summary = summarize_samples(&args.measure.summary, Samples::Store(&mut results), len, !args.system_wide, other_domains, meter.machine.clone(), idle_power)?;
+
+    Some(Report::new(&header, summary, len, exit_status, possible_multiple_wraps, baseline))
+}
+
+// Exits with an error. Exiting skips destructors, so the CPU time source is dropped first, which removes the cgroup the
+// target was started in.
+fn exit_removing_cgroup(cputime_source: Option<CpuTimeSource>) -> ! {
+    drop(cputime_source);
+    std::process::exit(1);
+}
+
+fn analyze(path: PathBuf, args: SummaryArgs) {
+    STATUS_TO_STDERR.store(args.format != Format::Text, Ordering::Relaxed);
+
+    let trace = match Trace::open(&path) {
+        Ok(trace) => trace,
+        Err(e) => {
+            eprintln!("Unable to read trace {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    };
+    let header = &trace.header;
+    check_summary_args(&args, trace.baseline.is_some());
+
+    status!("Trace of {:?} recorded on {} ({}) with {} counters", header.command.as_deref().unwrap_or(""), header.host.hostname, header.host.cpu, header.host.backend);
+    if !trace.complete {
+        status!("The trace has no end record, the recording was probably interrupted.");
+    }
+
+    let machine = Machine {
+        ncpus: header.host.cpus,
+        counter_of_cpu: header.counter_of_cpu.clone(),
+        core_of_cpu: header.core_of_cpu.clone()
+    };
+    let idle_power = args.idle_power.or(trace.baseline.as_ref().map(|b| b.power.cpu));
+    let Some(summary) = summarize_samples(&args, Samples::Trace(&trace, &path), trace.len, !header.system_wide, &header.other_domains, machine, idle_power) else {
+        std::process::exit(1);
+    };
+    let report = Report::new(header, summary, trace.len, trace.exit_status.clone(), trace.possible_multiple_wraps, trace.baseline.clone());
+
+    print_report(args.format, &report);
+}
+
+// Refuses options that cannot be summarized, before anything is measured
+fn check_summary_args(args: &SummaryArgs, calibrated: bool) {
+    if !(0.0..1.0).contains(&(args.startup_ignore + args.end_ignore)) || args.startup_ignore < 0.0 || args.end_ignore < 0.0 {
+        eprintln!("--startup-ignore and --end-ignore must not be negative, and must leave part of the run.");
+        std::process::exit(1);
+    }
+    if args.skip_start.is_some_and(|s| s < 0.0) || args.skip_end.is_some_and(|s| s < 0.0) {
+        eprintln!("--skip-start and --skip-end must not be negative.");
+        std::process::exit(1);
+    }
+    if args.model.needs_idle_power() && args.idle_power.is_none() && !calibrated {
+        eprintln!("The {} model needs the idle power of the CPU, pass --idle-power or --calibrate.", args.model);
+        std::process::exit(1);
+    }
+}
+
+// Refuses measuring options that do not go together, and summary options that cannot be summarized
+fn check_measure_args(args: &MeasureArgs) {
+    if args.calibrate_after && args.calibrate.is_none() {
+        eprintln!("--calibrate-after needs the length of the idle window from --calibrate.");
+        std::process::exit(1);
+    }
+    check_summary_args(&args.summary, args.calibrate.is_some());
+}
+
+// Measures the idle baseline, exits on errors
+fn calibrate(ctx: &dyn EnergySource, window: Duration, period: Duration, other_domains: &[Domain]) -> Baseline {
+    status!("Measuring the idle baseline for {} s, keep the machine idle", window.as_secs_f64());
+
+    match Baseline::measure(ctx, window, period, other_domains) {
+        Ok(baseline) => {
+            status!("Idle power is {} W package, {} W CPU", baseline.power.package, baseline.power.cpu);
+            baseline
+        }
+        Err(e) => {
+            eprintln!("Unable to measure the idle baseline: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+// Where the samples of a run are read back from, once more if the window depends on them
+enum Samples<'a> {
+    Store(&'a mut SampleStore),
+
+    Trace(&'a Trace, &'a Path)
+}
+
+impl Samples<'_> {
+    // Exits on errors
+    fn iter(&mut self) -> Box<dyn Iterator<Item = Result<CollectedPoint, std::io::Error>> + '_> {
+        match self {
+            Samples::Store(store) => match store.iter() {
+                Ok(samples) => samples,
+                Err(e) => {
+                    eprintln!("Unable to read samples back: {e}");
+                    std::process::exit(1);
+                }
+            },
+            Samples::Trace(trace, path) => match trace.points() {
+                Ok(samples) => Box::new(samples),
+                Err(e) => {
+                    eprintln!("Unable to read trace {}: {e}", path.display());
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+// The trimming chosen by the summary arguments
+fn trim_mode(args: &SummaryArgs) -> TrimMode {
+    if args.no_trim {
+        TrimMode::None
+    } else if args.steady_state {
+        TrimMode::SteadyState
+    } else if args.skip_start.is_some() || args.skip_end.is_some() {
+        TrimMode::Seconds { start: args.skip_start.unwrap_or(0.0), end: args.skip_end.unwrap_or(0.0) }
+    } else {
+        TrimMode::Fraction { start: args.startup_ignore, end: args.end_ignore }
+    }
+}
+
+// Computes the summary of a run, writing the samples to --export on the way. None when there are too few samples to
+// summarize, exits on errors.
+fn summarize_samples(args: &SummaryArgs, mut samples: Samples, len: usize, attribute: bool, other_domains: &[Domain], machine: Machine, idle_power: Option<f64>) -> Option<Summary> {
+    status!("Total datapoints collected: {len}");
+
+    if len <= 3 {
+        status!("Too few datapoints. Consider a longer-running program or reducing the sampling interval.");
+        return None;
+    }
+
+    let mut export = args.export.as_ref().map(|path| match SampleExport::create(path, other_domains) {
+        Ok(export) => {
+            status!("Writing samples to {}", path.display());
+            export
+        }
+        Err(e) => {
+            eprintln!("Unable to create {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    });
+
+    let options = SummaryOptions {
+        trim: trim_mode(args),
+        machine,
+        attribute,
+        per_core: args.per_core,
+        model: args.model,
+        idle_power
+    };
+
+    let window = match options.window(samples.iter(), len) {
+        Ok(window) => window,
+        Err(e) => {
+            eprintln!("Unable to process samples: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let summary = match summary::summarize(samples.iter(), window, &options, other_domains, export.as_mut()) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("Unable to process samples: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(export) = export {
+        if let Err(e) = export.finish() {
+            eprintln!("Unable to export samples: {e}");
+            std::process::exit(1);
+        }
+    }
+
+    if summary.skipped_samples > 0 {
+        status!("{} samples without the CPU time of the target were skipped", summary.skipped_samples);
+    }
+
+    Some(summary)
+}
+
+fn print_report(format: Format, report: &Report) {
+    match format {
+        Format::Text => print!("{report}"),
+        Format::Json => println!("{}", report.to_json())
+    }
+}
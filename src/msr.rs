@@ -0,0 +1,83 @@
+use std::{fs::File, os::unix::prelude::FileExt};
+
+use crate::source::{counter_mask, Units};
+
+// The power unit register has the same layout on Intel (MSR_RAPL_POWER_UNIT) and AMD (MSR_PWR_UNIT)
+const TIME_UNIT_MASK:       u64 = 0xF0000;
+const ENERGY_UNIT_MASK:     u64 = 0x1F00;
+const POWER_UNIT_MASK:      u64 = 0xF;
+
+/// RAPL energy status registers only implement the low 32 bits, on Intel and AMD alike.
+pub const RAPL_COUNTER_WIDTH: u32 = 32;
+
+/// Open `/dev/cpu/N/msr` file descriptors of a set of cores, with the units decoded from each core's power unit register.
+#[derive(Debug)]
+pub struct MsrCores {
+    cores: usize,
+
+    // Logical CPU id behind each fd
+    cpus: Vec<usize>,
+
+    core_msr_fds: Vec<File>,
+
+    units: Vec<Units>
+}
+
+pub fn read_u64_msr_with_fd(fd: &File, addr: u64) -> Result<u64, std::io::Error> {
+    let mut buffer: [u8; 8] = [0; 8];
+
+    fd.read_at(&mut buffer, addr)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+impl MsrCores {
+    /// Opens the MSR device of every CPU in `cpus`. Cores are then addressed by their index in `cpus`.
+    pub fn open(cpus: &[usize], power_unit_addr: u64) -> Result<MsrCores, std::io::Error> {
+        let mut core_msr_fds = Vec::<File>::new();
+        let mut units = Vec::<Units>::new();
+
+        for i in cpus {
+            let core_fd = File::options().read(true).write(false).open(format!("/dev/cpu/{}/msr", i))?;
+
+            let pwr_unit = read_u64_msr_with_fd(&core_fd, power_unit_addr)?;
+
+            let time_unit_raw: u64 = (pwr_unit & TIME_UNIT_MASK) >> 16;
+            let energy_unit_raw: u64 = (pwr_unit & ENERGY_UNIT_MASK) >> 8;
+            let power_unit_raw: u64 = pwr_unit & POWER_UNIT_MASK;
+
+            core_msr_fds.push(core_fd);
+            units.push(Units {
+                time: 0.5_f64.powi(time_unit_raw as i32),
+                energy: 0.5_f64.powi(energy_unit_raw as i32),
+                power: 0.5_f64.powi(power_unit_raw as i32)
+            });
+        }
+
+        Ok(MsrCores {
+            cores: cpus.len(),
+            cpus: cpus.to_vec(),
+            core_msr_fds,
+            units
+        })
+    }
+
+    pub fn get_cores(&self) -> usize { self.cores }
+
+    pub fn cpu(&self, core: usize) -> usize { self.cpus[core] }
+
+    pub fn read_u64(&self, core: usize, addr: u64) -> Result<u64, std::io::Error> {
+        read_u64_msr_with_fd(&self.core_msr_fds[core], addr)
+    }
+
+    /// Reads an energy status register of a core, masked to the counter bits.
+    pub fn read_counter(&self, core: usize, addr: u64) -> Result<u64, std::io::Error> {
+        Ok(self.read_u64(core, addr)? & counter_mask(RAPL_COUNTER_WIDTH))
+    }
+
+    /// Reads the same energy status register on every core, masked to the counter bits.
+    pub fn read_counters(&self, addr: u64) -> Result<Vec<u64>, std::io::Error> {
+        (0..self.cores).map(|core| self.read_counter(core, addr)).collect()
+    }
+
+    pub fn units(&self, core: usize) -> Units { self.units[core] }
+}
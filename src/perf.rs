@@ -0,0 +1,201 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::source::{Domain, EnergySource, Units};
+use crate::topology::{parse_cpu_list, Topology};
+use crate::util::{invalid_data, read_sysfs};
+
+const POWER_PMU_ROOT: &str = "/sys/bus/event_source/devices/power";
+
+// Size of the first published perf_event_attr layout (PERF_ATTR_SIZE_VER0), accepted by every kernel.
+const PERF_ATTR_SIZE_VER0: u32 = 64;
+
+// perf_event_attr up to and including config1, all flag bits left cleared.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64
+}
+
+#[derive(Debug)]
+struct PerfCounter {
+    domain: Domain,
+
+    // perf counts of this event are multiplied by scale to get Joules
+    scale: f64,
+
+    fd: File
+}
+
+/// Reads RAPL counters through the perf_event `power` PMU, which honours `perf_event_paranoid` instead of requiring root.
+#[derive(Debug)]
+pub struct PerfContext {
+    // One per event and package, packages in cpumask order
+    counters: Vec<PerfCounter>,
+
+    // The cpumask of the PMU, one CPU per package
+    package_cpus: Vec<usize>,
+
+    topology: Option<Topology>
+}
+
+// The events exposed by the power PMU, in the order we report them.
+const EVENTS: &[(&str, Domain)] = &[
+    ("energy-pkg", Domain::Package),
+    ("energy-cores", Domain::Core),
+    ("energy-gpu", Domain::Uncore),
+    ("energy-ram", Domain::Dram),
+    ("energy-psys", Domain::Psys)
+];
+
+// Parses "event=0x02" from an event description.
+fn parse_event_config(path: &Path) -> Result<u64, std::io::Error> {
+    let content = fs::read_to_string(path)?;
+
+    for term in content.trim().split(',') {
+        if let Some(value) = term.strip_prefix("event=") {
+            return u64::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|e| invalid_data(format!("{}: {e}", path.display())));
+        }
+    }
+
+    Err(invalid_data(format!("{}: no event= term", path.display())))
+}
+
+fn perf_event_open(attr: &PerfEventAttr, cpu: usize) -> Result<File, std::io::Error> {
+    // pid = -1 with a cpu counts system-wide on that CPU, which is the only mode the power PMU accepts.
+    let fd = unsafe {
+        libc::syscall(libc::SYS_perf_event_open, attr as *const PerfEventAttr, -1 as libc::pid_t, cpu as libc::c_int, -1 as libc::c_int, 0 as libc::c_ulong)
+    };
+
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd as i32) })
+}
+
+fn read_count(fd: &File) -> Result<u64, std::io::Error> {
+    let mut buffer: [u8; 8] = [0; 8];
+
+    let mut reader = fd;
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_ne_bytes(buffer))
+}
+
+impl PerfContext {
+    pub fn new() -> Result<PerfContext, std::io::Error> {
+        PerfContext::with_root(POWER_PMU_ROOT)
+    }
+
+    /// Opens every energy event of the power PMU described under `root`, once per package.
+    /// The PMU cpumask lists one CPU of each package.
+    pub fn with_root(root: impl AsRef<Path>) -> Result<PerfContext, std::io::Error> {
+        let root = root.as_ref();
+
+        let type_path = root.join("type");
+        let pmu_type = read_sysfs::<u32>(&type_path)?;
+        let cpus = parse_cpu_list(&fs::read_to_string(root.join("cpumask"))?);
+
+        let mut counters = Vec::<PerfCounter>::new();
+
+        for (event, domain) in EVENTS {
+            let event_path: PathBuf = root.join("events").join(event);
+            if !event_path.exists() {
+                continue;
+            }
+
+            let scale_path = root.join("events").join(format!("{event}.scale"));
+            let scale = read_sysfs::<f64>(&scale_path)?;
+
+            let attr = PerfEventAttr {
+                type_: pmu_type,
+                size: PERF_ATTR_SIZE_VER0,
+                config: parse_event_config(&event_path)?,
+                ..Default::default()
+            };
+
+            for &cpu in &cpus {
+                let fd = perf_event_open(&attr, cpu).map_err(|e| std::io::Error::new(e.kind(), format!("perf_event_open {event} on CPU {cpu}: {e}")))?;
+
+                counters.push(PerfCounter {
+                    domain: *domain,
+                    scale,
+                    fd
+                });
+            }
+        }
+
+        if counters.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No energy events under {}", root.display())));
+        }
+
+        Ok(PerfContext {
+            counters,
+            package_cpus: cpus,
+            topology: Topology::detect().ok()
+        })
+    }
+
+    fn counters_of(&self, domain: Domain) -> impl Iterator<Item = &PerfCounter> {
+        self.counters.iter().filter(move |counter| counter.domain == domain)
+    }
+}
+
+impl EnergySource for PerfContext {
+    fn name(&self) -> &'static str { "perf" }
+
+    fn domains(&self) -> Vec<Domain> {
+        EVENTS.iter().map(|(_, domain)| *domain).filter(|domain| self.counters_of(*domain).next().is_some()).collect()
+    }
+
+    // energy-cores is counted once per package.
+    fn get_cores(&self) -> usize { self.counters_of(Domain::Core).count() }
+
+    fn get_packages(&self) -> usize { self.package_cpus.len() }
+
+    fn read_raw_per_package(&self, domain: Domain) -> Result<Vec<u64>, std::io::Error> {
+        self.counters_of(domain).map(|counter| read_count(&counter.fd)).collect()
+    }
+
+    fn all_core_raw(&self) -> Result<Vec<u64>, std::io::Error> {
+        self.read_raw_per_package(Domain::Core)
+    }
+
+    fn core_of_cpu(&self, cpu: usize) -> Option<usize> {
+        let topology = self.topology.as_ref()?;
+        let package = topology.package_of(cpu)?;
+
+        let index = self.package_cpus.iter().position(|c| topology.package_of(*c) == Some(package))?;
+        (index < self.get_cores()).then_some(index)
+    }
+
+    // The kernel accumulates the hardware counter into a 64-bit count, which never wraps in practice.
+    fn counter_width(&self, _domain: Domain) -> u32 { 64 }
+
+    // perf only publishes the energy scale, time is in perf clock nanoseconds.
+    fn units(&self) -> Units {
+        let energy = self.counters.first().map(|counter| counter.scale).unwrap_or(0.0);
+
+        Units {
+            time: 1e-9,
+            energy,
+            power: energy
+        }
+    }
+
+    // Every event has its own scale.
+    fn energy_unit(&self, domain: Domain) -> f64 {
+        self.counters_of(domain).next().map(|counter| counter.scale).unwrap_or(0.0)
+    }
+}
@@ -0,0 +1,220 @@
+use std::fs::{self, File};
+use std::os::unix::prelude::FileExt;
+use std::path::{Path, PathBuf};
+
+use crate::source::{Domain, EnergySource, Units};
+use crate::topology::Topology;
+use crate::util::{invalid_data, read_sysfs};
+
+const POWERCAP_ROOT: &str = "/sys/class/powercap";
+
+// Zones of the RAPL control type are named intel-rapl:<package>[:<subzone>], also on AMD.
+const RAPL_ZONE_PREFIX: &str = "intel-rapl:";
+
+// Powercap reports energy in uJ
+const MICRO: f64 = 1e-6;
+
+#[derive(Debug)]
+struct Zone {
+    domain: Domain,
+
+    path: PathBuf,
+
+    energy_fd: File,
+
+    max_energy_range_uj: u64,
+
+    // Highest constraint_N_max_power_uw of the zone, when the driver reports one
+    max_power_uw: Option<u64>
+}
+
+/// Reads RAPL counters from the Linux powercap sysfs tree, which does not need the msr driver.
+#[derive(Debug)]
+pub struct PowercapContext {
+    zones: Vec<Zone>,
+
+    topology: Option<Topology>
+}
+
+fn read_u64_with_fd(fd: &File) -> Result<u64, std::io::Error> {
+    let mut buffer: [u8; 32] = [0; 32];
+
+    let len = fd.read_at(&mut buffer, 0)?;
+    let content = String::from_utf8_lossy(&buffer[..len]);
+
+    content.trim().parse::<u64>().map_err(invalid_data)
+}
+
+fn domain_from_zone_name(name: &str) -> Option<Domain> {
+    match name {
+        "core" => Some(Domain::Core),
+        "uncore" => Some(Domain::Uncore),
+        "dram" => Some(Domain::Dram),
+        "psys" => Some(Domain::Psys),
+        _ if name.starts_with("package-") => Some(Domain::Package),
+        _ => None
+    }
+}
+
+// intel-rapl:1:0 sorts as [1, 0], so zones come out grouped by package in numeric order
+fn zone_sort_key(path: &Path) -> Vec<u32> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+    name.trim_start_matches(RAPL_ZONE_PREFIX).split(':').filter_map(|n| n.parse::<u32>().ok()).collect()
+}
+
+impl PowercapContext {
+    pub fn new() -> Result<PowercapContext, std::io::Error> {
+        PowercapContext::with_root(POWERCAP_ROOT)
+    }
+
+    /// Scans the RAPL zones below a powercap root, usually `/sys/class/powercap`.
+    pub fn with_root(root: impl AsRef<Path>) -> Result<PowercapContext, std::io::Error> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(root.as_ref())?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(RAPL_ZONE_PREFIX)))
+            .collect();
+        paths.sort_by_key(|path| zone_sort_key(path));
+
+        let mut zones = Vec::<Zone>::new();
+
+        for path in paths {
+            let name = fs::read_to_string(path.join("name"))?;
+            let Some(domain) = domain_from_zone_name(name.trim()) else { continue };
+
+            // energy_uj is root-only on most kernels since the PLATYPUS mitigation, fail early with the path in the message.
+            let energy_fd = File::open(path.join("energy_uj")).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.join("energy_uj").display())))?;
+            let max_energy_range_uj = read_sysfs::<u64>(&path.join("max_energy_range_uj"))?;
+            let max_power_uw = (0..)
+                .map(|n| path.join(format!("constraint_{n}_max_power_uw")))
+                .take_while(|p| p.exists())
+                .filter_map(|p| read_sysfs::<u64>(&p).ok())
+                .filter(|uw| *uw != 0)
+                .max();
+
+            zones.push(Zone {
+                domain,
+                path,
+                energy_fd,
+                max_energy_range_uj,
+                max_power_uw
+            });
+        }
+
+        if zones.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No RAPL zones under {}", root.as_ref().display())));
+        }
+
+        Ok(PowercapContext {
+            zones,
+            topology: Topology::detect().ok()
+        })
+    }
+
+    fn zones_of(&self, domain: Domain) -> impl Iterator<Item = &Zone> {
+        self.zones.iter().filter(move |zone| zone.domain == domain)
+    }
+
+    fn max_energy_range_uj(&self, domain: Domain) -> u64 {
+        self.zones_of(domain).map(|zone| zone.max_energy_range_uj).max().unwrap_or(0)
+    }
+
+    /// Sysfs directories backing a domain, one per package.
+    pub fn zone_paths(&self, domain: Domain) -> Vec<&Path> {
+        self.zones_of(domain).map(|zone| zone.path.as_path()).collect()
+    }
+}
+
+impl EnergySource for PowercapContext {
+    fn name(&self) -> &'static str { "powercap" }
+
+    fn domains(&self) -> Vec<Domain> {
+        let mut domains = Vec::<Domain>::new();
+        for zone in &self.zones {
+            if !domains.contains(&zone.domain) {
+                domains.push(zone.domain);
+            }
+        }
+
+        domains
+    }
+
+    // One core zone per package, there is no per-core counter in powercap.
+    fn get_cores(&self) -> usize { self.zones_of(Domain::Core).count() }
+
+    fn get_packages(&self) -> usize { self.zones_of(Domain::Package).count() }
+
+    // Every package has its own zone of each domain, in package order.
+    fn read_raw_per_package(&self, domain: Domain) -> Result<Vec<u64>, std::io::Error> {
+        self.zones_of(domain).map(|zone| read_u64_with_fd(&zone.energy_fd)).collect()
+    }
+
+    fn all_core_raw(&self) -> Result<Vec<u64>, std::io::Error> {
+        self.read_raw_per_package(Domain::Core)
+    }
+
+    // Core zones follow package order.
+    fn core_of_cpu(&self, cpu: usize) -> Option<usize> {
+        let package = self.topology.as_ref()?.package_of(cpu)?;
+
+        (package < self.get_cores()).then_some(package)
+    }
+
+    fn counter_width(&self, domain: Domain) -> u32 {
+        u64::BITS - self.max_energy_range_uj(domain).leading_zeros()
+    }
+
+    fn units(&self) -> Units {
+        Units {
+            time: MICRO,
+            energy: MICRO,
+            power: MICRO
+        }
+    }
+
+    fn max_power(&self, domain: Domain) -> Option<f64> {
+        self.zones_of(domain).filter_map(|zone| zone.max_power_uw).max().map(|uw| uw as f64 * MICRO)
+    }
+
+    // The counter wraps at max_energy_range_uj, which is usually not a power of two.
+    fn counter_max(&self, domain: Domain) -> u64 {
+        self.max_energy_range_uj(domain)
+    }
+
+    fn counter_range(&self, domain: Domain) -> f64 {
+        (self.max_energy_range_uj(domain) + 1) as f64 * MICRO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A powercap root with a single package zone, in a directory of its own
+    fn fake_root(name: &str, max_energy_range_uj: u64) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("powermeter-test-{name}-{}", std::process::id()));
+        let zone = root.join("intel-rapl:0");
+        fs::create_dir_all(&zone).unwrap();
+        fs::write(zone.join("name"), "package-0\n").unwrap();
+        fs::write(zone.join("energy_uj"), "0\n").unwrap();
+        fs::write(zone.join("max_energy_range_uj"), format!("{max_energy_range_uj}\n")).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn energy_delta_wraps_at_max_energy_range() {
+        // A common value, not a power of two
+        let max = 262143328850;
+        let root = fake_root("wrap", max);
+        let ctx = PowercapContext::with_root(&root).unwrap();
+
+        assert_eq!(ctx.counter_max(Domain::Package), max);
+        assert!((ctx.energy_delta(Domain::Package, 1000, 3000) - 2000.0 * MICRO).abs() < 1e-12);
+        // 100 uJ up to the maximum, one more to wrap to 0, then 50
+        assert!((ctx.energy_delta(Domain::Package, max - 100, 50) - 151.0 * MICRO).abs() < 1e-12);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
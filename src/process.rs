@@ -0,0 +1,580 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::Child;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cgroup::Cgroup;
+use crate::report::ExitStatus;
+use crate::util;
+
+/// The process being measured: one we started, or one that was already running.
+#[derive(Debug)]
+pub enum Target {
+    Spawned(Child),
+
+    Attached {
+        pid: u32,
+
+        // Start time from /proc/<pid>/stat, to notice the PID being reused after the process exits
+        start_time: u64
+    }
+}
+
+/// Cumulative CPU time of a process tree, summed over all threads.
+///
+/// `/proc/<pid>/schedstat` only covers the thread-group leader, so every thread in `/proc/<pid>/task` is read instead.
+/// Threads that exit keep their last reading, and threads that start and exit between two readings are covered by the
+/// utime and stime of their process, which include exited threads at clock tick resolution.
+///
+/// Once a process has exited and been waited for, its final CPU time, including everything after the last reading, is
+/// in the cutime and cstime of its parent. Exited processes whose parent is still tracked are therefore counted
+/// through the parent; the others keep their last reading.
+#[derive(Debug, Default)]
+pub struct CpuTimeTracker {
+    // Processes in the tree at the last update
+    processes: HashMap<u32, ProcessTime>,
+
+    // PIDs found by the last update, the root first
+    pids: Vec<u32>,
+
+    // CPU time of exited processes that is not in the cutime and cstime of a tracked parent, in nanoseconds
+    exited: u64,
+
+    // Highest total returned so far
+    total: u64
+}
+
+#[derive(Debug, Default)]
+struct ProcessTime {
+    ppid: u32,
+
+    // Last schedstat reading of every thread seen, by thread ID
+    threads: HashMap<u32, u64>,
+
+    // CPU time of the process itself, in nanoseconds
+    own: u64,
+
+    // cutime and cstime when the process was first seen, and now, in nanoseconds
+    reaped_baseline: u64,
+
+    reaped: u64,
+
+    // Whether it is in the tree at the current update
+    alive: bool
+}
+
+// The fields of /proc/<pid>/stat the tracker needs
+#[derive(Debug)]
+struct ProcStat {
+    state: String,
+
+    ppid: u32,
+
+    // utime + stime, and cutime + cstime, in nanoseconds
+    cputime: u64,
+
+    children_cputime: u64
+}
+
+// One process of the tree as read by an update, with the schedstat reading of every thread it still has
+#[derive(Debug)]
+struct ProcessReading {
+    pid: u32,
+
+    stat: ProcStat,
+
+    threads: Option<Vec<(u32, u64)>>
+}
+
+/// Where the target ran: its CPU time on every logical CPU, as far as the sampled threads tell.
+///
+/// Each thread is read from `/proc/<tid>/schedstat` and the `processor` field of `/proc/<tid>/stat`, and the CPU time
+/// it used since the previous reading is charged to the CPU it last ran on. Migrations between two readings are
+/// therefore only seen at the sampling interval, and threads that start and exit between two readings are missed.
+#[derive(Debug, Default)]
+pub struct CpuPlacement {
+    // Last schedstat reading of every thread seen, by thread ID
+    threads: HashMap<u32, u64>,
+
+    // Cumulative CPU time charged to every logical CPU, in nanoseconds
+    per_cpu: Vec<u64>
+}
+
+/// How the CPU time of the target is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Accounting {
+    // Walk the process tree in /proc
+    Proc,
+
+    // Run the target in a cgroup of its own and read its cpu.stat
+    Cgroup
+}
+
+/// Where the CPU time of the target is read from.
+#[derive(Debug)]
+pub enum CpuTimeSource {
+    // The process tree below a PID
+    Proc {
+        root: u32,
+
+        tracker: CpuTimeTracker
+    },
+
+    // Every process in a cgroup, including short-lived ones and those that left the tree
+    Cgroup(Cgroup)
+}
+
+/// Whether the target is still running.
+#[derive(Debug, Clone)]
+pub enum TargetState {
+    Running,
+
+    // The exit status is only known for processes we started
+    Exited(Option<ExitStatus>)
+}
+
+// Fields of a /proc/<pid>/stat line after the command name, which may itself contain spaces and parentheses
+fn parse_stat_fields(stat: &str) -> Option<Vec<String>> {
+    let (_, fields) = stat.rsplit_once(')')?;
+
+    Some(fields.split_whitespace().map(|f| f.to_owned()).collect())
+}
+
+fn stat_fields(pid: u32) -> Result<Vec<String>, std::io::Error> {
+    parse_stat_fields(&fs::read_to_string(format!("/proc/{pid}/stat"))?)
+        .ok_or_else(|| util::invalid_data(format!("Malformed /proc/{pid}/stat")))
+}
+
+// starttime is field 22, the 20th after the command name
+fn stat_start_time(fields: &[String]) -> Option<u64> {
+    fields.get(19).and_then(|f| f.parse::<u64>().ok())
+}
+
+// The CPU time in nanoseconds, first of the fields of a schedstat file
+fn parse_schedstat(sched: &str) -> Option<u64> {
+    sched.split_whitespace().next().and_then(|t| t.parse::<u64>().ok())
+}
+
+/// Time the process started after boot, in clock ticks.
+pub fn start_time(pid: u32) -> Result<u64, std::io::Error> {
+    stat_start_time(&stat_fields(pid)?).ok_or_else(|| util::invalid_data(format!("No start time in /proc/{pid}/stat")))
+}
+
+/// CPU time of every thread of a process from `/proc/<pid>/task/<tid>/schedstat`, in nanoseconds, by thread ID.
+pub fn thread_cputimes(pid: u32) -> Result<Vec<(u32, u64)>, std::io::Error> {
+    let mut threads = Vec::<(u32, u64)>::new();
+
+    for entry in fs::read_dir(format!("/proc/{pid}/task"))? {
+        let Some(tid) = entry?.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) else { continue };
+
+        // The thread may exit while we are reading
+        if let Ok(sched) = fs::read_to_string(format!("/proc/{pid}/task/{tid}/schedstat")) {
+            if let Some(cputime) = parse_schedstat(&sched) {
+                threads.push((tid, cputime));
+            }
+        }
+    }
+
+    Ok(threads)
+}
+
+impl ProcStat {
+    fn parse(fields: &[String], ticks_per_second: u64) -> ProcStat {
+        let field = |n: usize| fields.get(n).and_then(|f| f.parse::<u64>().ok()).unwrap_or(0);
+
+        // Counting from the state, field 3: ppid is field 4, utime, stime, cutime and cstime are fields 14 to 17
+        ProcStat {
+            state: fields.first().cloned().unwrap_or_default(),
+            ppid: field(1) as u32,
+            cputime: (field(11) + field(12)) * 1000000000 / ticks_per_second,
+            children_cputime: (field(13) + field(14)) * 1000000000 / ticks_per_second
+        }
+    }
+}
+
+fn proc_stat(pid: u32) -> Result<ProcStat, std::io::Error> {
+    Ok(ProcStat::parse(&stat_fields(pid)?, util::clock_ticks_per_second()))
+}
+
+/// Every process below `pid`, children first. Uses `/proc/<pid>/task/<tid>/children`, or scans `/proc` on kernels
+/// without it.
+pub fn descendants(pid: u32) -> Vec<u32> {
+    if !Path::new(&format!("/proc/{pid}")).exists() {
+        return Vec::new();
+    }
+
+    match descendants_from_children_files(pid) {
+        Ok(pids) => pids,
+        Err(_) => descendants_from_scan(pid)
+    }
+}
+
+fn children_of(pid: u32) -> Result<Vec<u32>, std::io::Error> {
+    let mut children = Vec::<u32>::new();
+
+    for entry in fs::read_dir(format!("/proc/{pid}/task"))? {
+        let path = entry?.path().join("children");
+        match fs::read_to_string(&path) {
+            Ok(content) => children.extend(content.split_whitespace().filter_map(|c| c.parse::<u32>().ok())),
+            // Only fails for every thread when the kernel lacks CONFIG_PROC_CHILDREN
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !path.parent().is_some_and(|p| p.exists()) => { }
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok(children)
+}
+
+fn descendants_from_children_files(pid: u32) -> Result<Vec<u32>, std::io::Error> {
+    let mut pids = Vec::<u32>::new();
+    let mut queue = vec![pid];
+
+    while let Some(parent) = queue.pop() {
+        match children_of(parent) {
+            Ok(children) => {
+                pids.extend(&children);
+                queue.extend(children);
+            }
+            // The process exited while we were walking the tree
+            Err(_) if parent != pid && !Path::new(&format!("/proc/{parent}")).exists() => { }
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok(pids)
+}
+
+fn descendants_from_scan(pid: u32) -> Vec<u32> {
+    let mut children_by_parent = HashMap::<u32, Vec<u32>>::new();
+
+    for entry in fs::read_dir("/proc").into_iter().flatten().filter_map(|e| e.ok()) {
+        let Some(child) = entry.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) else { continue };
+        if let Ok(stat) = proc_stat(child) {
+            children_by_parent.entry(stat.ppid).or_default().push(child);
+        }
+    }
+
+    let mut pids = Vec::<u32>::new();
+    let mut queue = vec![pid];
+    while let Some(parent) = queue.pop() {
+        if let Some(children) = children_by_parent.get(&parent) {
+            pids.extend(children);
+            queue.extend(children);
+        }
+    }
+
+    pids
+}
+
+/// Thread IDs of a process, from `/proc/<pid>/task`.
+pub fn threads(pid: u32) -> Result<Vec<u32>, std::io::Error> {
+    let mut tids = Vec::<u32>::new();
+
+    for entry in fs::read_dir(format!("/proc/{pid}/task"))? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) {
+            tids.push(tid);
+        }
+    }
+
+    Ok(tids)
+}
+
+// CPU time of a thread in nanoseconds, and the logical CPU it last ran on
+fn thread_placement(tid: u32) -> Result<(u64, usize), std::io::Error> {
+    let cputime = parse_schedstat(&fs::read_to_string(format!("/proc/{tid}/schedstat"))?);
+
+    // processor is field 39, the 37th after the command name
+    let processor = stat_fields(tid)?.get(36).and_then(|f| f.parse::<usize>().ok());
+
+    match (cputime, processor) {
+        (Some(cputime), Some(processor)) => Ok((cputime, processor)),
+        _ => Err(util::invalid_data(format!("Malformed /proc/{tid}/stat or schedstat")))
+    }
+}
+
+/// Command line of a process, with the arguments separated by spaces.
+pub fn command_line(pid: u32) -> Result<String, std::io::Error> {
+    let cmdline = fs::read(format!("/proc/{pid}/cmdline"))?;
+
+    Ok(cmdline.split(|b| *b == 0).filter(|arg| !arg.is_empty()).map(String::from_utf8_lossy).collect::<Vec<_>>().join(" "))
+}
+
+impl CpuTimeTracker {
+    pub fn new() -> CpuTimeTracker {
+        CpuTimeTracker::default()
+    }
+
+    /// Reads the CPU time of `root` and all its descendants, and returns the cumulative CPU time of the tree, in nanoseconds.
+    pub fn update(&mut self, root: u32) -> u64 {
+        let mut pids = vec![root];
+        pids.extend(descendants(root));
+
+        let readings = pids.iter().copied()
+            .filter_map(|pid| Some(ProcessReading { pid, stat: proc_stat(pid).ok()?, threads: thread_cputimes(pid).ok() }))
+            .collect();
+
+        self.pids = pids;
+        self.record(readings)
+    }
+
+    // Accounts for the processes found in the tree by one update, and returns the cumulative CPU time of the tree
+    fn record(&mut self, readings: Vec<ProcessReading>) -> u64 {
+        for process in self.processes.values_mut() {
+            process.alive = false;
+        }
+
+        for ProcessReading { pid, stat, threads } in readings {
+            let process = self.processes.entry(pid).or_insert_with(|| ProcessTime {
+                reaped_baseline: stat.children_cputime,
+                ..Default::default()
+            });
+
+            // A zombie has no threads left, but its stat holds the final CPU time
+            if let Some(threads) = threads {
+                process.threads.extend(threads);
+            }
+
+            process.ppid = stat.ppid;
+            process.own = process.own.max(process.threads.values().sum::<u64>()).max(stat.cputime);
+            process.reaped = process.reaped.max(stat.children_cputime.saturating_sub(process.reaped_baseline));
+            process.alive = stat.state != "X";
+        }
+
+        let exited: Vec<u32> = self.processes.iter().filter(|(_, p)| !p.alive).map(|(pid, _)| *pid).collect();
+        for pid in exited {
+            let process = self.processes.remove(&pid).unwrap();
+            if !self.processes.get(&process.ppid).is_some_and(|parent| parent.alive) {
+                self.exited += process.own + process.reaped;
+            }
+        }
+
+        let total = self.exited + self.processes.values().map(|p| p.own + p.reaped).sum::<u64>();
+
+        // CPU time never goes backwards, even when a reading races with a process being waited for
+        self.total = self.total.max(total);
+        self.total
+    }
+
+    /// Processes in the tree at the last update, the root first.
+    pub fn pids(&self) -> &[u32] {
+        &self.pids
+    }
+}
+
+impl CpuPlacement {
+    pub fn new() -> CpuPlacement {
+        CpuPlacement::default()
+    }
+
+    /// Reads the given threads and returns the cumulative CPU time charged to every logical CPU, in nanoseconds.
+    pub fn update(&mut self, tids: &[u32]) -> &[u64] {
+        // The thread may exit while we are reading
+        let readings: Vec<(u32, u64, usize)> = tids.iter()
+            .filter_map(|tid| thread_placement(*tid).ok().map(|(cputime, cpu)| (*tid, cputime, cpu)))
+            .collect();
+
+        self.charge(&readings, tids)
+    }
+
+    // Charges the CPU time of every (thread, CPU time, CPU) reading since the last one to the CPU, and forgets the
+    // threads not in `tids`
+    fn charge(&mut self, readings: &[(u32, u64, usize)], tids: &[u32]) -> &[u64] {
+        for &(tid, cputime, cpu) in readings {
+            let last = self.threads.insert(tid, cputime).unwrap_or(0);
+
+            if self.per_cpu.len() <= cpu {
+                self.per_cpu.resize(cpu + 1, 0);
+            }
+            self.per_cpu[cpu] += cputime.saturating_sub(last);
+        }
+
+        // Threads are not kept once they are gone, so a reused thread ID starts over
+        let alive: HashSet<u32> = tids.iter().copied().collect();
+        self.threads.retain(|tid, _| alive.contains(tid));
+
+        &self.per_cpu
+    }
+}
+
+impl CpuTimeSource {
+    pub fn accounting(&self) -> Accounting {
+        match self {
+            CpuTimeSource::Proc { .. } => Accounting::Proc,
+            CpuTimeSource::Cgroup(_) => Accounting::Cgroup
+        }
+    }
+
+    /// Every thread of the target at the moment. With /proc accounting, of the processes found by the last `read`,
+    /// which already walked the tree.
+    pub fn threads(&self) -> Vec<u32> {
+        match self {
+            CpuTimeSource::Proc { tracker, .. } => {
+                tracker.pids().iter().flat_map(|pid| threads(*pid).unwrap_or_default()).collect()
+            }
+            CpuTimeSource::Cgroup(cgroup) => cgroup.threads()
+        }
+    }
+
+    /// Cumulative CPU time of the target, in nanoseconds. 0 if it cannot be read.
+    pub fn read(&mut self) -> u64 {
+        match self {
+            CpuTimeSource::Proc { root, tracker } => tracker.update(*root),
+            CpuTimeSource::Cgroup(cgroup) => cgroup.cpu_stat().map(|stat| stat.usage_ns()).unwrap_or(0)
+        }
+    }
+}
+
+// Whether the stat fields are of the process that started at `start_time`, and it is not a zombie
+fn is_running(fields: &[String], start_time: u64) -> bool {
+    fields.first().is_some_and(|state| state != "Z") && stat_start_time(fields) == Some(start_time)
+}
+
+impl Target {
+    /// Attaches to a running process. Fails if there is no such process.
+    pub fn attach(pid: u32) -> Result<Target, std::io::Error> {
+        Ok(Target::Attached {
+            pid,
+            start_time: start_time(pid)?
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        match self {
+            Target::Spawned(child) => child.id(),
+            Target::Attached { pid, .. } => *pid
+        }
+    }
+
+    /// Checks whether the target has exited, without blocking. A process we did not start counts as exited once it
+    /// is a zombie, its PID is gone or has been reused.
+    pub fn poll(&mut self) -> TargetState {
+        match self {
+            Target::Spawned(child) => match child.try_wait() {
+                Ok(Some(status)) => TargetState::Exited(Some(ExitStatus {
+                    code: status.code(),
+                    signal: status.signal()
+                })),
+                _ => TargetState::Running
+            },
+            Target::Attached { pid, start_time } => match stat_fields(*pid) {
+                Ok(fields) if is_running(&fields, *start_time) => TargetState::Running,
+                _ => TargetState::Exited(None)
+            }
+        }
+    }
+
+    /// Reaps a target we started. Nothing to do for an attached process.
+    pub fn wait(&mut self) {
+        if let Target::Spawned(child) = self {
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sleeping process with a command name that contains spaces and parentheses, utime 250, stime 50, cutime 30,
+    // cstime 20 ticks, started 123456 ticks after boot and last run on CPU 5
+    const STAT: &str = "4242 (a (b) c) S 4000 4242 4000 34817 4242 4194304 1200 0 0 0 250 50 30 20 20 0 3 0 123456 \
+        225280000 5000 18446744073709551615 94000000000000 94000000100000 140700000000000 0 0 0 0 0 0 0 0 0 17 5 0 0 \
+        0 0 0 94000000200000 94000000300000 94000001000000 140700000001000 140700000001100 140700000001100 \
+        140700000002000 0\n";
+
+    const MS: u64 = 1000000;
+
+    fn stat(ppid: u32, cputime_ms: u64, children_cputime_ms: u64) -> ProcStat {
+        ProcStat {
+            state: "S".to_owned(),
+            ppid,
+            cputime: cputime_ms * MS,
+            children_cputime: children_cputime_ms * MS
+        }
+    }
+
+    fn reading(pid: u32, stat: ProcStat, threads: &[(u32, u64)]) -> ProcessReading {
+        ProcessReading {
+            pid,
+            stat,
+            threads: Some(threads.iter().map(|(tid, ms)| (*tid, ms * MS)).collect())
+        }
+    }
+
+    #[test]
+    fn stat_and_schedstat() {
+        let fields = parse_stat_fields(STAT).unwrap();
+        let stat = ProcStat::parse(&fields, 100);
+
+        assert_eq!(stat.state, "S");
+        assert_eq!(stat.ppid, 4000);
+        assert_eq!(stat.cputime, 3000 * MS);
+        assert_eq!(stat.children_cputime, 500 * MS);
+        assert_eq!(stat_start_time(&fields), Some(123456));
+        assert_eq!(fields.get(36).map(|f| f.as_str()), Some("5"));
+
+        assert!(is_running(&fields, 123456));
+        assert!(!is_running(&fields, 123457));
+        assert!(!is_running(&parse_stat_fields(&STAT.replacen(") S ", ") Z ", 1)).unwrap(), 123456));
+
+        assert!(parse_stat_fields("4242 (truncated").is_none());
+        assert_eq!(parse_schedstat("1234567890 98765 42\n"), Some(1234567890));
+        assert_eq!(parse_schedstat(""), None);
+    }
+
+    #[test]
+    fn process_time_is_the_largest_reading() {
+        let mut tracker = CpuTimeTracker::new();
+
+        // The threads are ahead of utime and stime, which only count whole ticks
+        assert_eq!(tracker.record(vec![reading(1, stat(0, 1000, 500), &[(1, 1200)])]), 1200 * MS);
+
+        // A thread that started and exited between two readings only shows in utime and stime
+        assert_eq!(tracker.record(vec![reading(1, stat(0, 2000, 500), &[(1, 1300)])]), 2000 * MS);
+
+        // A child, and a child of the child that has been reaped: its time is in the cutime of the child only
+        assert_eq!(tracker.record(vec![
+            reading(1, stat(0, 2000, 500), &[(1, 1300)]),
+            reading(2, stat(1, 100, 0), &[(2, 150)])
+        ]), 2150 * MS);
+        assert_eq!(tracker.record(vec![
+            reading(1, stat(0, 2000, 500), &[(1, 1300)]),
+            reading(2, stat(1, 100, 300), &[(2, 150)])
+        ]), 2450 * MS);
+    }
+
+    #[test]
+    fn total_never_decreases() {
+        let mut tracker = CpuTimeTracker::new();
+        assert_eq!(tracker.record(vec![reading(1, stat(0, 0, 0), &[(1, 100), (3, 500)])]), 600 * MS);
+
+        // Thread 3 exited, and its time is not in utime and stime yet
+        assert_eq!(tracker.record(vec![reading(1, stat(0, 0, 0), &[(1, 200)])]), 700 * MS);
+
+        assert_eq!(tracker.record(vec![
+            reading(1, stat(0, 0, 0), &[(1, 200)]),
+            reading(2, stat(1, 0, 0), &[(2, 400)])
+        ]), 1100 * MS);
+
+        // The child was waited for, but the cutime of its parent was read just before
+        assert_eq!(tracker.record(vec![reading(1, stat(0, 0, 0), &[(1, 200)])]), 1100 * MS);
+
+        // Its final time, including what it used after the last reading
+        assert_eq!(tracker.record(vec![reading(1, stat(0, 0, 450), &[(1, 200)])]), 1150 * MS);
+    }
+
+    #[test]
+    fn placement_charges_the_last_cpu() {
+        let mut placement = CpuPlacement::new();
+
+        assert_eq!(placement.charge(&[(1, 100, 0), (2, 50, 2)], &[1, 2]), &[100, 0, 50]);
+        // Thread 1 migrated to CPU 1, thread 2 exited
+        assert_eq!(placement.charge(&[(1, 180, 1)], &[1]), &[100, 80, 50]);
+        // A new thread reusing ID 2 starts over
+        assert_eq!(placement.charge(&[(1, 180, 1), (2, 10, 0)], &[1, 2]), &[110, 80, 50]);
+    }
+}
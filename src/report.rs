@@ -0,0 +1,686 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::attribution::Model;
+use crate::baseline::Baseline;
+use crate::cpu::CpuInfo;
+use crate::process::Accounting;
+use crate::source::Domain;
+use crate::stats::{self, Statistics, TTest};
+use crate::summary::Summary;
+use crate::trace::TraceHeader;
+
+/// p-value below which a difference between two commands is reported as significant.
+pub const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+/// Version of the JSON report layout. Bumped whenever a field is renamed, removed or changes meaning.
+///
+/// - 2: `attributed` of `energy` and `power` is `null` when measuring the whole system
+/// - 3: `trim` says how the window was chosen, `startup_ignore` and `end_ignore` are `null` unless trimming by fraction,
+///   and `first_sample` and `last_sample` bound the window exactly
+pub const REPORT_SCHEMA_VERSION: u32 = 3;
+
+/// How the summary is printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    // Human readable summary
+    Text,
+
+    // One JSON document, see README for the schema
+    Json
+}
+
+/// Machine the measurement was taken on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Host {
+    pub hostname: String,
+
+    // uname -r
+    pub kernel: String,
+
+    // e.g. "GenuineIntel family 0x06 model 0xcf"
+    pub cpu: String,
+
+    pub vendor_id: String,
+
+    pub family: u32,
+
+    pub model: u32,
+
+    // Online logical CPUs
+    pub cpus: usize,
+
+    // Name of the energy source, e.g. intel-msr
+    pub backend: String,
+
+    pub powermeter_version: String
+}
+
+/// Which part of the run the summary covers.
+#[derive(Debug, Clone, Serialize)]
+pub struct Trim {
+    // How the window was chosen: fraction, seconds, none or steady-state
+    pub mode: &'static str,
+
+    // Fractions of the samples dropped at the start and at the end, when trimming by fraction
+    pub startup_ignore: Option<f64>,
+
+    pub end_ignore: Option<f64>,
+
+    // Seconds dropped at the start and at the end, when trimming by time
+    pub skip_start: Option<f64>,
+
+    pub skip_end: Option<f64>,
+
+    // Index of the first and last sample of the window
+    pub first_sample: usize,
+
+    pub last_sample: usize,
+
+    // Start and end of the window, in seconds since sampling started
+    pub start_s: f64,
+
+    pub end_s: f64
+}
+
+/// How the target terminated. `code` is `None` when it was killed by a signal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExitStatus {
+    pub code: Option<i32>,
+
+    pub signal: Option<i32>
+}
+
+/// Energy in Joules, or average power in Watts, of every domain over the measured window.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DomainValues {
+    pub package: f64,
+
+    // Package share of every socket, in socket order
+    pub sockets: Vec<f64>,
+
+    pub cpu: f64,
+
+    // Share of `cpu` of every core counter, only filled in when asked for
+    pub cores: Vec<f64>,
+
+    // Part of `cpu` attributed to the target, None when measuring the whole system
+    pub attributed: Option<f64>,
+
+    // Extra domains (uncore, dram, psys) by name
+    pub domains: BTreeMap<String, f64>
+}
+
+/// Summary of one measurement, printed as text or serialized as JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub schema_version: u32,
+
+    // Seconds since the Unix epoch when sampling started
+    pub start_time: u64,
+
+    pub host: Host,
+
+    pub command: Option<String>,
+
+    // Process that was measured
+    pub pid: Option<u32>,
+
+    // How the CPU time of the target was measured, None without a target
+    pub accounting: Option<Accounting>,
+
+    // cgroup the CPU time of the target was read from
+    pub cgroup: Option<PathBuf>,
+
+    // How energy was attributed to the target, None when measuring the whole system
+    pub model: Option<Model>,
+
+    // None if the target was still running when measuring stopped
+    pub exit_status: Option<ExitStatus>,
+
+    // Length of the measured window
+    pub duration_ns: u64,
+
+    pub sampling_period_ms: u64,
+
+    // Samples collected over the whole run
+    pub samples: usize,
+
+    pub trim: Trim,
+
+    // Whether the CPU figures come from the package counter because there is no core counter
+    pub cpu_from_package: bool,
+
+    // Joules
+    pub energy: DomainValues,
+
+    // Watts
+    pub power: DomainValues,
+
+    // Samples taken further apart than the counter wrap time
+    pub possible_multiple_wraps: usize,
+
+    // Idle power measured before and after the target ran, if calibrated
+    pub baseline: Option<Baseline>,
+
+    // Energy and power above the baseline, without an attributed part
+    pub net_energy: Option<DomainValues>,
+
+    pub net_power: Option<DomainValues>
+}
+
+/// Title of a domain in the text summary.
+pub fn domain_title(domain: Domain) -> &'static str {
+    match domain {
+        Domain::Package => "System",
+        Domain::Core => "CPU",
+        Domain::Uncore => "Uncore",
+        Domain::Dram => "DRAM",
+        Domain::Psys => "Platform"
+    }
+}
+
+/// Statistics of every domain over repeated runs, with the same fields as `DomainValues`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DomainStatistics {
+    pub package: Statistics,
+
+    pub sockets: Vec<Statistics>,
+
+    pub cpu: Statistics,
+
+    pub cores: Vec<Statistics>,
+
+    pub attributed: Option<Statistics>,
+
+    pub domains: BTreeMap<String, Statistics>
+}
+
+/// Summary of repeated runs of the same target, printed as text or serialized as JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunsReport {
+    pub schema_version: u32,
+
+    pub host: Host,
+
+    pub command: Option<String>,
+
+    pub model: Option<Model>,
+
+    // Runs the statistics are over, and runs before them that were discarded
+    pub runs: usize,
+
+    pub warmup: usize,
+
+    // Length of the measured window, in seconds
+    pub duration: Statistics,
+
+    pub energy: DomainStatistics,
+
+    pub power: DomainStatistics,
+
+    // Above the idle baseline, if calibrated
+    pub net_energy: Option<DomainStatistics>,
+
+    pub net_power: Option<DomainStatistics>,
+
+    // Every measured run, warmup runs left out
+    pub reports: Vec<Report>
+}
+
+/// One quantity of a command set against the same quantity of the baseline command.
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricComparison {
+    // `duration`, or `<domain>_energy` and `<domain>_power` with `package`, `cpu`, `attributed` and the extra domains
+    pub metric: String,
+
+    pub baseline_mean: f64,
+
+    pub mean: f64,
+
+    // mean - baseline_mean, and relative to baseline_mean, None if that is 0
+    pub difference: f64,
+
+    pub relative: Option<f64>,
+
+    pub test: TTest,
+
+    // Whether the p-value is below `SIGNIFICANCE_LEVEL`
+    pub significant: bool
+}
+
+/// A command compared with the baseline command.
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub command: String,
+
+    pub baseline: String,
+
+    pub metrics: Vec<MetricComparison>
+}
+
+/// Summary of several commands run in interleaved rounds, each compared with the first.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompareReport {
+    pub schema_version: u32,
+
+    pub host: Host,
+
+    pub model: Option<Model>,
+
+    // Rounds the statistics are over, and rounds before them that were discarded
+    pub rounds: usize,
+
+    pub warmup: usize,
+
+    // Statistics of every command, the baseline first
+    pub commands: Vec<RunsReport>,
+
+    // Every command after the first, against the first
+    pub comparisons: Vec<Comparison>
+}
+
+// Extra domains of a report in the order they are read, rather than by name.
+fn extra_domains(values: &BTreeMap<String, f64>) -> impl Iterator<Item = (Domain, f64)> + '_ {
+    [Domain::Uncore, Domain::Dram, Domain::Psys].into_iter().filter_map(|d| values.get(d.name()).map(|v| (d, *v)))
+}
+
+fn read_proc_string(path: &str) -> String {
+    fs::read_to_string(path).map(|s| s.trim().to_owned()).unwrap_or_default()
+}
+
+impl Host {
+    pub fn detect(cpu: &CpuInfo, backend: &str) -> Host {
+        Host {
+            hostname: read_proc_string("/proc/sys/kernel/hostname"),
+            kernel: read_proc_string("/proc/sys/kernel/osrelease"),
+            cpu: cpu.to_string(),
+            vendor_id: cpu.vendor_id.clone(),
+            family: cpu.family,
+            model: cpu.model,
+            cpus: num_cpus::get(),
+            backend: backend.to_owned(),
+            powermeter_version: env!("CARGO_PKG_VERSION").to_owned()
+        }
+    }
+}
+
+impl DomainValues {
+    /// Average power over `duration_ns` of every energy in `self`.
+    pub fn per_second(&self, duration_ns: u64) -> DomainValues {
+        let secs = duration_ns as f64 / 1000000000.0;
+
+        DomainValues {
+            package: self.package / secs,
+            sockets: self.sockets.iter().map(|e| e / secs).collect(),
+            cpu: self.cpu / secs,
+            cores: self.cores.iter().map(|e| e / secs).collect(),
+            attributed: self.attributed.map(|e| e / secs),
+            domains: self.domains.iter().map(|(name, e)| (name.clone(), e / secs)).collect()
+        }
+    }
+}
+
+impl DomainStatistics {
+    /// Statistics of every domain over a non-empty set of values.
+    pub fn of(values: &[&DomainValues]) -> DomainStatistics {
+        let stats = |value: &dyn Fn(&DomainValues) -> f64| Statistics::of(&values.iter().map(|v| value(v)).collect::<Vec<_>>());
+        let per_index = |list: &dyn Fn(&DomainValues) -> &Vec<f64>| {
+            let len = values.iter().map(|v| list(v).len()).min().unwrap_or(0);
+            (0..len).map(|i| stats(&|v| list(v)[i])).collect::<Vec<_>>()
+        };
+
+        DomainStatistics {
+            package: stats(&|v| v.package),
+            sockets: per_index(&|v| &v.sockets),
+            cpu: stats(&|v| v.cpu),
+            cores: per_index(&|v| &v.cores),
+            attributed: values.iter().all(|v| v.attributed.is_some()).then(|| stats(&|v| v.attributed.unwrap_or(0.0))),
+            domains: values[0].domains.keys().map(|d| (d.clone(), stats(&|v| *v.domains.get(d).unwrap_or(&0.0)))).collect()
+        }
+    }
+}
+
+impl RunsReport {
+    /// Aggregates the reports of a non-empty set of runs.
+    pub fn new(reports: Vec<Report>, warmup: usize) -> RunsReport {
+        let first = &reports[0];
+        let energy: Vec<&DomainValues> = reports.iter().map(|r| &r.energy).collect();
+        let power: Vec<&DomainValues> = reports.iter().map(|r| &r.power).collect();
+        let net_energy: Option<Vec<&DomainValues>> = reports.iter().map(|r| r.net_energy.as_ref()).collect();
+        let net_power: Option<Vec<&DomainValues>> = reports.iter().map(|r| r.net_power.as_ref()).collect();
+
+        RunsReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            host: first.host.clone(),
+            command: first.command.clone(),
+            model: first.model,
+            runs: reports.len(),
+            warmup,
+            duration: Statistics::of(&reports.iter().map(|r| r.duration_ns as f64 / 1000000000.0).collect::<Vec<_>>()),
+            energy: DomainStatistics::of(&energy),
+            power: DomainStatistics::of(&power),
+            net_energy: net_energy.map(|v| DomainStatistics::of(&v)),
+            net_power: net_power.map(|v| DomainStatistics::of(&v)),
+            reports
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+// Every quantity that is compared between commands, by metric name
+fn metrics(report: &RunsReport) -> Vec<(String, &Statistics)> {
+    let mut metrics = vec![("duration".to_owned(), &report.duration)];
+
+    for (kind, values) in [("energy", &report.energy), ("power", &report.power)] {
+        metrics.push((format!("package_{kind}"), &values.package));
+        metrics.push((format!("cpu_{kind}"), &values.cpu));
+        if let Some(attributed) = &values.attributed {
+            metrics.push((format!("attributed_{kind}"), attributed));
+        }
+        for domain in [Domain::Uncore, Domain::Dram, Domain::Psys] {
+            if let Some(stats) = values.domains.get(domain.name()) {
+                metrics.push((format!("{domain}_{kind}"), stats));
+            }
+        }
+    }
+
+    metrics
+}
+
+// Row title of a metric, as in the other tables
+fn metric_title(metric: &str) -> String {
+    let Some((domain, kind)) = metric.rsplit_once('_') else { return "Duration (s)".to_owned() };
+
+    let title: &str = match domain {
+        "package" => domain_title(Domain::Package),
+        "cpu" => domain_title(Domain::Core),
+        "attributed" => "  Process",
+        other => [Domain::Uncore, Domain::Dram, Domain::Psys].into_iter().find(|d| d.name() == other).map_or(other, |d| domain_title(d))
+    };
+
+    if kind == "energy" { format!("{title} energy (J)") } else { format!("{title} Power (W)") }
+}
+
+impl Comparison {
+    /// Compares every metric of `report` with that of `baseline`. Both need at least two runs.
+    pub fn new(baseline: &RunsReport, report: &RunsReport) -> Comparison {
+        let baseline_metrics = metrics(baseline);
+
+        let metrics = metrics(report).into_iter()
+            .filter_map(|(metric, stats)| {
+                let (_, baseline_stats) = baseline_metrics.iter().find(|(m, _)| *m == metric)?;
+                let test = stats::welch_t_test(baseline_stats, stats);
+                let difference = stats.mean - baseline_stats.mean;
+
+                Some(MetricComparison {
+                    metric,
+                    baseline_mean: baseline_stats.mean,
+                    mean: stats.mean,
+                    difference,
+                    relative: (baseline_stats.mean != 0.0).then(|| difference / baseline_stats.mean),
+                    significant: test.p_value < SIGNIFICANCE_LEVEL,
+                    test
+                })
+            })
+            .collect();
+
+        Comparison {
+            command: report.command.clone().unwrap_or_default(),
+            baseline: baseline.command.clone().unwrap_or_default(),
+            metrics
+        }
+    }
+}
+
+impl CompareReport {
+    /// Compares every command with the first. Every command needs at least two rounds.
+    pub fn new(commands: Vec<RunsReport>, warmup: usize) -> CompareReport {
+        let baseline = &commands[0];
+
+        CompareReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            host: baseline.host.clone(),
+            model: baseline.model,
+            rounds: baseline.runs,
+            warmup,
+            comparisons: commands[1..].iter().map(|report| Comparison::new(baseline, report)).collect(),
+            commands
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl fmt::Display for CompareReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        match self.warmup {
+            0 => writeln!(f, "Compared {} commands over {} rounds:", self.commands.len(), self.rounds)?,
+            warmup => writeln!(f, "Compared {} commands over {} rounds, after {warmup} warmup rounds:", self.commands.len(), self.rounds)?
+        }
+        for (i, report) in self.commands.iter().enumerate() {
+            let command = report.command.as_deref().unwrap_or("");
+            if i == 0 {
+                writeln!(f, "  [{i}] {command:?} (baseline)")?;
+            } else {
+                writeln!(f, "  [{i}] {command:?}")?;
+            }
+        }
+        if let Some(model) = self.model {
+            writeln!(f, "Energy is attributed to the target with the {model} model.")?;
+        }
+
+        for (i, comparison) in self.comparisons.iter().enumerate() {
+            writeln!(f)?;
+            writeln!(f, "[{}] against [0]:", i + 1)?;
+            writeln!(f, "{:<28}{:>14}{:>14}{:>14}{:>11}{:>11}", "", "baseline", "mean", "difference", "relative", "p-value")?;
+
+            for metric in &comparison.metrics {
+                let relative = metric.relative.map_or("-".to_owned(), |r| format!("{:+.2}%", r * 100.0));
+                let marker = if metric.significant { " *" } else { "" };
+
+                writeln!(f, "{:<28}{:>14.4}{:>14.4}{:>+14.4}{relative:>11}{:>11.4}{marker}", metric_title(&metric.metric), metric.baseline_mean, metric.mean, metric.difference, metric.test.p_value)?;
+            }
+        }
+
+        writeln!(f)?;
+        writeln!(f, "* significant at the {}% level (Welch's t-test)", SIGNIFICANCE_LEVEL * 100.0)
+    }
+}
+
+// One row of the statistics table
+fn statistics_row(f: &mut fmt::Formatter<'_>, title: &str, stats: &Statistics) -> fmt::Result {
+    let ci = match stats.ci95 {
+        Some([low, high]) => format!("[{low:.4}, {high:.4}]"),
+        None => "-".to_owned()
+    };
+
+    writeln!(f, "{title:<28}{:>14.4}{:>14.4}{:>14.4}{:>14.4}{:>14.4}   {ci}", stats.mean, stats.median, stats.std_dev, stats.min, stats.max)
+}
+
+// Rows of every domain, as energy in Joules or power in Watts
+fn domain_statistics_rows(f: &mut fmt::Formatter<'_>, values: &DomainStatistics, unit: &str) -> fmt::Result {
+    let rows = |name: &str| if unit == "J" { format!("{name} energy (J)") } else { format!("{name} Power (W)") };
+
+    statistics_row(f, &rows(domain_title(Domain::Package)), &values.package)?;
+    if values.sockets.len() > 1 {
+        for (socket, stats) in values.sockets.iter().enumerate() {
+            statistics_row(f, &rows(&format!("  Socket {socket}")), stats)?;
+        }
+    }
+    statistics_row(f, &rows(domain_title(Domain::Core)), &values.cpu)?;
+    for (core, stats) in values.cores.iter().enumerate() {
+        statistics_row(f, &rows(&format!("  Core {core}")), stats)?;
+    }
+    if let Some(attributed) = &values.attributed {
+        statistics_row(f, &rows("  Process"), attributed)?;
+    }
+    for domain in [Domain::Uncore, Domain::Dram, Domain::Psys] {
+        if let Some(stats) = values.domains.get(domain.name()) {
+            statistics_row(f, &rows(domain_title(domain)), stats)?;
+        }
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for RunsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        match self.warmup {
+            0 => writeln!(f, "Over {} runs:", self.runs)?,
+            warmup => writeln!(f, "Over {} runs, after {warmup} warmup runs:", self.runs)?
+        }
+        if let Some(model) = self.model {
+            writeln!(f, "Energy is attributed to the target with the {model} model.")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:<28}{:>14}{:>14}{:>14}{:>14}{:>14}   95% CI", "", "mean", "median", "std dev", "min", "max")?;
+        statistics_row(f, "Duration (s)", &self.duration)?;
+        domain_statistics_rows(f, &self.energy, "J")?;
+        domain_statistics_rows(f, &self.power, "W")?;
+
+        if let (Some(net_energy), Some(net_power)) = (&self.net_energy, &self.net_power) {
+            writeln!(f)?;
+            writeln!(f, "Above the idle baseline:")?;
+            domain_statistics_rows(f, net_energy, "J")?;
+            domain_statistics_rows(f, net_power, "W")?;
+        }
+
+        let wraps: usize = self.reports.iter().map(|r| r.possible_multiple_wraps).sum();
+        if wraps > 0 {
+            writeln!(f)?;
+            writeln!(f, "Warning: {wraps} samples were taken further apart than the counter wrap time.")?;
+            writeln!(f, "         The counters may have wrapped more than once, energy is possibly undercounted. Use a shorter period.")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exit status: {code}"),
+            (None, Some(signal)) => write!(f, "signal: {signal}"),
+            (None, None) => f.write_str("unknown status")
+        }
+    }
+}
+
+impl Report {
+    /// The report of the run `header` describes, from the summary of its `samples` samples. With a baseline, the
+    /// energy and power above it are reported too.
+    pub fn new(header: &TraceHeader, summary: Summary, samples: usize, exit_status: Option<ExitStatus>, possible_multiple_wraps: usize, baseline: Option<Baseline>) -> Report {
+        let net_energy = baseline.as_ref().map(|b| b.subtract(&summary.energy, summary.duration_ns));
+
+        Report {
+            schema_version: REPORT_SCHEMA_VERSION,
+            start_time: header.start_time,
+            host: header.host.clone(),
+            command: header.command.clone(),
+            pid: header.pid,
+            accounting: header.accounting,
+            cgroup: header.cgroup.clone(),
+            model: summary.model,
+            exit_status,
+            duration_ns: summary.duration_ns,
+            sampling_period_ms: header.period_ms,
+            samples,
+            trim: summary.trim,
+            cpu_from_package: !header.has_core(),
+            power: summary.energy.per_second(summary.duration_ns),
+            energy: summary.energy,
+            possible_multiple_wraps,
+            baseline,
+            net_power: net_energy.as_ref().map(|e| e.per_second(summary.duration_ns)),
+            net_energy
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // Report has no maps with non-string keys, serialization cannot fail
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+// The summary printed at the end of a run, kept as it always was for people reading it.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time_ns = self.duration_ns;
+
+        writeln!(f)?;
+        writeln!(f, "Measured window: {}s to {}s since sampling started (trim: {}).", self.trim.start_s, self.trim.end_s, self.trim.mode)?;
+        writeln!(f, "During {time_ns} ns ({}s) of running: ", time_ns as f64 / 1000000000.0)?;
+        writeln!(f, "    {} J package energy is consumed.", self.energy.package)?;
+        if self.energy.sockets.len() > 1 {
+            for (socket, energy) in self.energy.sockets.iter().enumerate() {
+                writeln!(f, "        {energy} J by socket {socket}.")?;
+            }
+        }
+        writeln!(f, "    {} J energy is consumed by the CPU.", self.energy.cpu)?;
+        for (core, energy) in self.energy.cores.iter().enumerate() {
+            writeln!(f, "        {energy} J by core {core}.")?;
+        }
+        if let Some(attributed) = self.energy.attributed {
+            match self.model {
+                Some(model) => writeln!(f, "        {attributed} J energy can be attributed to the target ({model} model).")?,
+                None => writeln!(f, "        {attributed} J energy can be attributed to the target.")?
+            }
+        }
+        for (domain, energy) in extra_domains(&self.energy.domains) {
+            writeln!(f, "    {energy} J {domain} energy is consumed.")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "System Power is {} W", self.power.package)?;
+        if self.power.sockets.len() > 1 {
+            for (socket, power) in self.power.sockets.iter().enumerate() {
+                writeln!(f, "  Socket {socket} Power is {power} W")?;
+            }
+        }
+        writeln!(f, "CPU Power is {} W", self.power.cpu)?;
+        for (core, power) in self.power.cores.iter().enumerate() {
+            writeln!(f, "  Core {core} Power is {power} W")?;
+        }
+        if let Some(attributed) = self.power.attributed {
+            writeln!(f, "  Process CPU Power is {attributed} W")?;
+        }
+        for (domain, power) in extra_domains(&self.power.domains) {
+            writeln!(f, "{} Power is {power} W", domain_title(domain))?;
+        }
+
+        if let (Some(baseline), Some(net_energy), Some(net_power)) = (&self.baseline, &self.net_energy, &self.net_power) {
+            writeln!(f)?;
+            writeln!(f, "Idle baseline over {}s: {} W package, {} W CPU.", baseline.duration_ns as f64 / 1000000000.0, baseline.power.package, baseline.power.cpu)?;
+            writeln!(f, "Above the idle baseline:")?;
+            writeln!(f, "    {} J package energy is consumed.", net_energy.package)?;
+            writeln!(f, "    {} J energy is consumed by the CPU.", net_energy.cpu)?;
+            for (domain, energy) in extra_domains(&net_energy.domains) {
+                writeln!(f, "    {energy} J {domain} energy is consumed.")?;
+            }
+            writeln!(f, "System Power is {} W", net_power.package)?;
+            writeln!(f, "CPU Power is {} W", net_power.cpu)?;
+            for (domain, power) in extra_domains(&net_power.domains) {
+                writeln!(f, "{} Power is {power} W", domain_title(domain))?;
+            }
+        }
+
+        if self.possible_multiple_wraps > 0 {
+            writeln!(f)?;
+            writeln!(f, "Warning: {} samples were taken further apart than the counter wrap time.", self.possible_multiple_wraps)?;
+            writeln!(f, "         The counters may have wrapped more than once, energy is possibly undercounted. Use a shorter period.")?;
+        }
+
+        Ok(())
+    }
+}
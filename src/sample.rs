@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::source::Domain;
+use crate::util::invalid_data;
+
+const TRACE_HEADER: &str = "# powermeter samples v3";
+
+/// One sample of the measurement loop. Energies are deltas since the previous sample, in Joules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedPoint {
+    // Nanoseconds since sampling started
+    pub time: u64,
+
+    pub package: f64,
+
+    // Package energy delta of every socket
+    pub sockets: Vec<f64>,
+
+    pub cpu_power: f64,
+
+    // Energy delta of every core counter, summing to `cpu_power`. Empty without a core counter.
+    pub cores: Vec<f64>,
+
+    // Deltas of the extra domains of the run, in the same order
+    pub other: Vec<f64>,
+
+    // Cumulative CPU time of the target, in nanoseconds
+    pub sched: u64,
+
+    // Cumulative CPU time of the target on every logical CPU, as far as the sampled threads tell, in nanoseconds.
+    // Empty without a target.
+    pub sched_cpus: Vec<u64>,
+
+    // Cumulative busy time of every logical CPU, in nanoseconds. Empty without a target.
+    pub busy_cpus: Vec<u64>
+}
+
+/// Where the samples of a run are kept: in memory, or streamed to a trace file so memory stays bounded.
+#[derive(Debug)]
+pub enum SampleStore {
+    Memory(Vec<CollectedPoint>),
+
+    Disk {
+        path: PathBuf,
+
+        writer: BufWriter<File>,
+
+        len: usize
+    }
+}
+
+fn format_list<T: ToString>(values: &[T]) -> String {
+    if values.is_empty() {
+        return "-".to_owned();
+    }
+
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn parse_list<T: std::str::FromStr>(field: &str) -> Result<Vec<T>, std::io::Error>
+    where T::Err: std::fmt::Display
+{
+    if field == "-" {
+        return Ok(Vec::new());
+    }
+
+    field.split(',').map(|v| v.parse::<T>().map_err(invalid_data)).collect()
+}
+
+impl CollectedPoint {
+    // time, sched, package, cpu_power, sockets, cores, other, sched_cpus and busy_cpus, tab separated
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", self.time, self.sched, self.package, self.cpu_power, format_list(&self.sockets), format_list(&self.cores), format_list(&self.other), format_list(&self.sched_cpus), format_list(&self.busy_cpus))
+    }
+
+    fn from_line(line: &str) -> Result<CollectedPoint, std::io::Error> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 9 {
+            return Err(invalid_data(format!("Expected 9 fields in sample line, got {}", fields.len())));
+        }
+
+        Ok(CollectedPoint {
+            time: fields[0].parse::<u64>().map_err(invalid_data)?,
+            sched: fields[1].parse::<u64>().map_err(invalid_data)?,
+            package: fields[2].parse::<f64>().map_err(invalid_data)?,
+            cpu_power: fields[3].parse::<f64>().map_err(invalid_data)?,
+            sockets: parse_list(fields[4])?,
+            cores: parse_list(fields[5])?,
+            other: parse_list(fields[6])?,
+            sched_cpus: parse_list(fields[7])?,
+            busy_cpus: parse_list(fields[8])?
+        })
+    }
+}
+
+impl SampleStore {
+    pub fn memory() -> SampleStore {
+        SampleStore::Memory(Vec::new())
+    }
+
+    /// Creates a trace file at `path`, recording which extra domains the `other` energies belong to.
+    pub fn disk(path: impl AsRef<Path>, other_domains: &[Domain]) -> Result<SampleStore, std::io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut writer = BufWriter::new(File::create(&path)?);
+
+        writeln!(writer, "{TRACE_HEADER}")?;
+        writeln!(writer, "# other: {}", other_domains.iter().map(|d| d.name()).collect::<Vec<_>>().join(","))?;
+
+        Ok(SampleStore::Disk {
+            path,
+            writer,
+            len: 0
+        })
+    }
+
+    pub fn push(&mut self, point: CollectedPoint) -> Result<(), std::io::Error> {
+        match self {
+            SampleStore::Memory(points) => points.push(point),
+            SampleStore::Disk { writer, len, .. } => {
+                writeln!(writer, "{}", point.to_line())?;
+                *len += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SampleStore::Memory(points) => points.len(),
+            SampleStore::Disk { len, .. } => *len
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Path of the trace file, if samples go to disk.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            SampleStore::Memory(_) => None,
+            SampleStore::Disk { path, .. } => Some(path)
+        }
+    }
+
+    /// Iterates over the samples in recording order. A disk store is flushed and read back line by line.
+    pub fn iter(&mut self) -> Result<Box<dyn Iterator<Item = Result<CollectedPoint, std::io::Error>> + '_>, std::io::Error> {
+        match self {
+            SampleStore::Memory(points) => Ok(Box::new(points.iter().cloned().map(Ok))),
+            SampleStore::Disk { path, writer, .. } => {
+                writer.flush()?;
+
+                let reader = BufReader::new(File::open(&*path)?);
+                Ok(Box::new(reader.lines().filter(|line| !line.as_ref().is_ok_and(|l| l.starts_with('#'))).map(|line| CollectedPoint::from_line(&line?))))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_round_trip() {
+        // A 32-bit counter that wrapped from 0xFFFFFF00 to 0x40 between the two readings, in 2^-14 J
+        let wrapped = ((0x1_0000_0000u64 - 0xFFFF_FF00) + 0x40) as f64 / 16384.0;
+        let point = CollectedPoint {
+            time: 100_000_000,
+            package: wrapped,
+            sockets: vec![wrapped - 0.1, 0.1],
+            cpu_power: 0.1 + 0.2,
+            cores: vec![0.1, 0.2],
+            other: vec![1.0 / 3.0, 0.0, 2.5e-7],
+            sched: 75_000_000,
+            sched_cpus: vec![0, 50_000_000, 25_000_000, u64::MAX],
+            busy_cpus: vec![1, 2, 3, 4]
+        };
+
+        let line = point.to_line();
+        assert_eq!(line.split('\t').count(), 9);
+        assert_eq!(CollectedPoint::from_line(&line).unwrap(), point);
+    }
+
+    #[test]
+    fn empty_lists_round_trip() {
+        let point = CollectedPoint {
+            time: 0,
+            package: 1.5,
+            sockets: vec![1.5],
+            cpu_power: 1.5,
+            cores: Vec::new(),
+            other: Vec::new(),
+            sched: 0,
+            sched_cpus: Vec::new(),
+            busy_cpus: Vec::new()
+        };
+
+        assert_eq!(point.to_line(), "0\t0\t1.5\t1.5\t1.5\t-\t-\t-\t-");
+        assert_eq!(CollectedPoint::from_line(&point.to_line()).unwrap(), point);
+        assert!(CollectedPoint::from_line("0\t0\t1.5\t1.5\t1.5\t-\t-\t-").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::amd::{self, AmdCpuContext};
+use crate::cpu::{CpuInfo, Vendor};
+use crate::intel::{self, IntelCpuContext};
+use crate::perf::PerfContext;
+use crate::powercap::PowercapContext;
+
+/// A RAPL power domain with its own energy counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Domain {
+    // Whole processor package
+    Package,
+
+    // CPU cores (PP0 on Intel, per-core counters on AMD)
+    Core,
+
+    // Uncore, usually the integrated GPU (PP1)
+    Uncore,
+
+    // Memory controller and DRAM
+    Dram,
+
+    // Whole platform (PSys)
+    Psys
+}
+
+/// Where energy counters are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    // MSRs if they can be opened, then perf, then powercap. CPUs the MSR tables refuse stay refused
+    Auto,
+
+    // /dev/cpu/N/msr, needs the msr module and root
+    Msr,
+
+    // perf_event power PMU, needs perf_event_paranoid <= 0 or CAP_PERFMON
+    Perf,
+
+    // /sys/class/powercap/intel-rapl
+    Powercap
+}
+
+/// Units decoded from the power unit register, in seconds, Joules and Watts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Units {
+    pub time: f64,
+
+    pub energy: f64,
+
+    pub power: f64
+}
+
+/// Anything we can read energy counters from.
+///
+/// Backends provide raw counter readings, masked to the counter width. Energy in Joules and
+/// wraparound-corrected deltas are derived from those by the provided methods.
+pub trait EnergySource: fmt::Debug {
+    /// Short identifier of the backend, e.g. `amd-msr`.
+    fn name(&self) -> &'static str;
+
+    /// Domains this source can read.
+    fn domains(&self) -> Vec<Domain>;
+
+    /// Number of core counters `all_core_energy` reports: one per physical core, or one per package
+    /// when the hardware only counts cores per package (Intel PP0, powercap, perf).
+    fn get_cores(&self) -> usize;
+
+    /// Number of packages (sockets) `read_energy_per_package` reports.
+    fn get_packages(&self) -> usize;
+
+    /// Reads the raw counter of a domain once per package, masked to `counter_width(domain)`.
+    fn read_raw_per_package(&self, domain: Domain) -> Result<Vec<u64>, std::io::Error>;
+
+    /// Reads the raw counter of every core, masked to `counter_width(Domain::Core)`. Empty if the source has no core domain.
+    fn all_core_raw(&self) -> Result<Vec<u64>, std::io::Error>;
+
+    /// Index in `all_core_energy` of the counter covering a logical CPU.
+    /// On sources without per-core counters this is the counter of the CPU's package.
+    fn core_of_cpu(&self, cpu: usize) -> Option<usize>;
+
+    /// Width of the raw counter of a domain, in bits.
+    fn counter_width(&self, domain: Domain) -> u32;
+
+    fn units(&self) -> Units;
+
+    /// Energy unit of a domain in Joules, when it differs from `units().energy`.
+    fn energy_unit(&self, _domain: Domain) -> f64 {
+        self.units().energy
+    }
+
+    /// Highest power the domain can draw in Watts, if the hardware reports it.
+    fn max_power(&self, _domain: Domain) -> Option<f64> {
+        None
+    }
+
+    /// Highest raw value of the counter of a domain, after which it wraps around to 0.
+    fn counter_max(&self, domain: Domain) -> u64 {
+        counter_mask(self.counter_width(domain))
+    }
+
+    /// Energy the counter of a domain holds before it wraps around, in Joules.
+    fn counter_range(&self, domain: Domain) -> f64 {
+        2_f64.powi(self.counter_width(domain) as i32) * self.energy_unit(domain)
+    }
+
+    /// Shortest time in which the counter of a domain can wrap around, at `max_power`.
+    /// Two readings further apart than this may be separated by more than one wraparound.
+    fn wrap_interval(&self, domain: Domain) -> Option<Duration> {
+        let max_power = self.max_power(domain).filter(|p| *p > 0.0)?;
+
+        Duration::try_from_secs_f64(self.counter_range(domain) / max_power).ok()
+    }
+
+    /// Longest sampling period that still sees every wraparound of the package and core counters at maximum power.
+    /// Half the shortest wrap interval, leaving room for scheduling jitter. `None` if the maximum power is unknown.
+    fn max_safe_period(&self) -> Option<Duration> {
+        [Domain::Package, Domain::Core].into_iter()
+            .filter(|d| self.domains().contains(d))
+            .filter_map(|d| self.wrap_interval(d))
+            .min()
+            .map(|wrap| wrap / 2)
+    }
+
+    /// Energy counted between two raw readings of a domain, in Joules, allowing for one wraparound at `counter_max`.
+    fn energy_delta(&self, domain: Domain, before: u64, after: u64) -> f64 {
+        counter_delta(self.counter_max(domain), before, after) as f64 * self.energy_unit(domain)
+    }
+
+    /// Reads the counter of a domain once per package, in Joules.
+    fn read_energy_per_package(&self, domain: Domain) -> Result<Vec<f64>, std::io::Error> {
+        let unit = self.energy_unit(domain);
+
+        Ok(self.read_raw_per_package(domain)?.into_iter().map(|raw| raw as f64 * unit).collect())
+    }
+
+    /// Reads the current counter value of a domain, in Joules. Per-core and per-package domains are summed.
+    fn read_energy(&self, domain: Domain) -> Result<f64, std::io::Error> {
+        match domain {
+            Domain::Core => self.all_core_energy_sum(),
+            _ => Ok(self.read_energy_per_package(domain)?.into_iter().sum())
+        }
+    }
+
+    /// Reads the counter of every core, in Joules. Empty if the source has no core domain.
+    fn all_core_energy(&self) -> Result<Vec<f64>, std::io::Error> {
+        let unit = self.energy_unit(Domain::Core);
+
+        Ok(self.all_core_raw()?.into_iter().map(|raw| raw as f64 * unit).collect())
+    }
+
+    /// Reads the counter of a single core, in Joules.
+    fn read_core_energy(&self, core: usize) -> Option<f64> {
+        self.all_core_energy().ok()?.get(core).copied()
+    }
+
+    fn read_package_energy(&self) -> Result<f64, std::io::Error> {
+        self.read_energy(Domain::Package)
+    }
+
+    fn read_package_energies(&self) -> Result<Vec<f64>, std::io::Error> {
+        self.read_energy_per_package(Domain::Package)
+    }
+
+    fn all_core_energy_sum(&self) -> Result<f64, std::io::Error> {
+        Ok(self.all_core_energy()?.into_iter().sum())
+    }
+}
+
+/// Mask keeping the low `width` bits of a raw counter.
+pub fn counter_mask(width: u32) -> u64 {
+    if width >= u64::BITS {
+        u64::MAX
+    } else {
+        (1_u64 << width) - 1
+    }
+}
+
+impl Domain {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Domain::Package => "package",
+            Domain::Core => "core",
+            Domain::Uncore => "uncore",
+            Domain::Dram => "dram",
+            Domain::Psys => "psys"
+        }
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Counts between two raw readings of a counter that wraps around to 0 after `max`, allowing for one wraparound.
+pub fn counter_delta(max: u64, before: u64, after: u64) -> u64 {
+    if after >= before {
+        after - before
+    } else {
+        (max - before.min(max)) + after + 1
+    }
+}
+
+// Everything is measured against the package counter, a source without one would report 0 J throughout
+fn with_package(ctx: Box<dyn EnergySource>) -> Result<Box<dyn EnergySource>, std::io::Error> {
+    if ctx.domains().contains(&Domain::Package) {
+        Ok(ctx)
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("The {} backend has no package energy counter on this machine.", ctx.name())))
+    }
+}
+
+/// Opens an energy source for the detected CPU with the requested backend. Sources without a package counter, e.g.
+/// a power PMU or powercap tree with only psys, are refused with `ErrorKind::Unsupported`.
+/// `Backend::Auto` falls back to perf and then powercap when the MSRs cannot be used, reporting the MSR error if all fail.
+pub fn open(cpu: &CpuInfo, backend: Backend) -> Result<Box<dyn EnergySource>, std::io::Error> {
+    let perf = || PerfContext::new().map(|ctx| Box::new(ctx) as Box<dyn EnergySource>).and_then(with_package);
+    let powercap = || PowercapContext::new().map(|ctx| Box::new(ctx) as Box<dyn EnergySource>).and_then(with_package);
+
+    match backend {
+        Backend::Msr => open_msr(cpu).and_then(with_package),
+        Backend::Perf => perf(),
+        Backend::Powercap => powercap(),
+        // A CPU refused by the model tables is refused by every backend, the others are only tried when the MSRs
+        // cannot be opened
+        Backend::Auto => match open_msr(cpu) {
+            Ok(ctx) => with_package(ctx),
+            Err(msr_err) if msr_err.kind() == std::io::ErrorKind::Unsupported => Err(msr_err),
+            Err(msr_err) => perf().or_else(|_| powercap()).map_err(|_| msr_err)
+        }
+    }
+}
+
+/// Opens the MSR register set matching the detected CPU.
+/// Fails with `ErrorKind::Unsupported` on CPUs without usable energy counters.
+pub fn open_msr(cpu: &CpuInfo) -> Result<Box<dyn EnergySource>, std::io::Error> {
+    let reason = match cpu.vendor {
+        Vendor::Amd => amd::unsupported_reason(cpu),
+        Vendor::Intel => intel::unsupported_reason(cpu),
+        Vendor::Unknown => Some(format!("Unknown CPU vendor {}.", cpu.vendor_id))
+    };
+
+    if let Some(reason) = reason {
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, reason));
+    }
+
+    match cpu.vendor {
+        Vendor::Amd => Ok(Box::new(AmdCpuContext::new()?)),
+        _ => Ok(Box::new(IntelCpuContext::new(cpu)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A single 32-bit counter counting in 2^-14 J, like most RAPL MSRs
+    #[derive(Debug)]
+    struct Rapl32;
+
+    impl EnergySource for Rapl32 {
+        fn name(&self) -> &'static str { "test" }
+
+        fn domains(&self) -> Vec<Domain> { vec![Domain::Package] }
+
+        fn get_cores(&self) -> usize { 0 }
+
+        fn get_packages(&self) -> usize { 1 }
+
+        fn read_raw_per_package(&self, _domain: Domain) -> Result<Vec<u64>, std::io::Error> { Ok(vec![0]) }
+
+        fn all_core_raw(&self) -> Result<Vec<u64>, std::io::Error> { Ok(Vec::new()) }
+
+        fn core_of_cpu(&self, _cpu: usize) -> Option<usize> { None }
+
+        fn counter_width(&self, _domain: Domain) -> u32 { 32 }
+
+        fn units(&self) -> Units {
+            Units { time: 1.0 / 1024.0, energy: 1.0 / 16384.0, power: 1.0 / 8.0 }
+        }
+    }
+
+    #[test]
+    fn counter_delta_allows_one_wraparound() {
+        assert_eq!(counter_delta(u32::MAX as u64, 10, 25), 15);
+        assert_eq!(counter_delta(u32::MAX as u64, 0xFFFF_FFF0, 0x10), 0x20);
+        assert_eq!(counter_delta(999, 990, 5), 15);
+    }
+
+    #[test]
+    fn energy_delta_wraps_a_32_bit_counter() {
+        let ctx = Rapl32;
+
+        assert_eq!(ctx.counter_max(Domain::Package), u32::MAX as u64);
+        assert_eq!(ctx.energy_delta(Domain::Package, 0xFFFF_FFF0, 0x10), 0x20 as f64 / 16384.0);
+        assert_eq!(ctx.energy_delta(Domain::Package, 100, 100), 0.0);
+    }
+}
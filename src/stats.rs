@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+
+/// Summary statistics of one quantity over repeated runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Statistics {
+    pub n: usize,
+
+    pub mean: f64,
+
+    pub median: f64,
+
+    // Sample standard deviation, 0 for a single value
+    pub std_dev: f64,
+
+    pub min: f64,
+
+    pub max: f64,
+
+    // 95% confidence interval of the mean from Student's t distribution, None for fewer than two values
+    pub ci95: Option<[f64; 2]>
+}
+
+/// Welch's t-test of the difference between two means, which does not assume equal variances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TTest {
+    // None when neither sample varies
+    pub t: Option<f64>,
+
+    // Welch-Satterthwaite degrees of freedom
+    pub df: Option<f64>,
+
+    // Two-sided
+    pub p_value: f64
+}
+
+impl Statistics {
+    /// Statistics of a non-empty set of values.
+    pub fn of(values: &[f64]) -> Statistics {
+        let n = values.len();
+        let mean = values.iter().sum::<f64>() / n as f64;
+
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let std_dev = if n > 1 { (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt() } else { 0.0 };
+        let ci95 = (n > 1).then(|| {
+            let half = t_quantile(0.975, (n - 1) as f64) * std_dev / (n as f64).sqrt();
+            [mean - half, mean + half]
+        });
+
+        Statistics {
+            n,
+            mean,
+            median: median(values),
+            std_dev,
+            min: sorted[0],
+            max: sorted[n - 1],
+            ci95
+        }
+    }
+
+    /// Variance of the mean, `std_dev² / n`.
+    pub fn variance_of_mean(&self) -> f64 {
+        self.std_dev.powi(2) / self.n as f64
+    }
+}
+
+// Natural logarithm of the gamma function, Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [76.18009172947146, -86.50532032941677, 24.01409824083091, -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS.iter().enumerate().fold(1.000000000190015, |sum, (i, c)| sum + c / (x + 1.0 + i as f64));
+
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+// Continued fraction of the incomplete beta function, by the modified Lentz method
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY { d = TINY; }
+    d = 1.0 / d;
+    let mut h = d;
+
+    for m in 1..300 {
+        let m = m as f64;
+
+        for numerator in [m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)), -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0))] {
+            d = 1.0 + numerator * d;
+            if d.abs() < TINY { d = TINY; }
+            c = 1.0 + numerator / c;
+            if c.abs() < TINY { c = TINY; }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+
+        if (d * c - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+
+    h
+}
+
+// Regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Welch's t-test of `b.mean - a.mean`. Both samples need at least two values.
+pub fn welch_t_test(a: &Statistics, b: &Statistics) -> TTest {
+    let (va, vb) = (a.variance_of_mean(), b.variance_of_mean());
+    let se = (va + vb).sqrt();
+
+    // Without any variance, the means either differ for sure or not at all
+    if se == 0.0 {
+        return TTest { t: None, df: None, p_value: if a.mean == b.mean { 1.0 } else { 0.0 } };
+    }
+
+    let t = (b.mean - a.mean) / se;
+    let df = (va + vb).powi(2) / (va.powi(2) / (a.n - 1) as f64 + vb.powi(2) / (b.n - 1) as f64);
+
+    TTest {
+        t: Some(t),
+        df: Some(df),
+        p_value: (2.0 * t_cdf(-t.abs(), df)).min(1.0)
+    }
+}
+
+/// Cumulative distribution function of Student's t distribution with `df` degrees of freedom, which need not be an integer.
+pub fn t_cdf(t: f64, df: f64) -> f64 {
+    let tail = incomplete_beta(df / 2.0, 0.5, df / (df + t * t)) / 2.0;
+
+    if t > 0.0 { 1.0 - tail } else { tail }
+}
+
+/// The `p` quantile of Student's t distribution, for `p` above 0.5.
+pub fn t_quantile(p: f64, df: f64) -> f64 {
+    let (mut low, mut high) = (0.0_f64, 1.0_f64);
+    while t_cdf(high, df) < p && high < 1e6 {
+        high *= 2.0;
+    }
+
+    for _ in 0..100 {
+        let mid = (low + high) / 2.0;
+        if t_cdf(mid, df) < p { low = mid; } else { high = mid; }
+    }
+
+    (low + high) / 2.0
+}
+
+// Median of a non-empty set of values
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let n = sorted.len();
+
+    if n % 2 == 1 { sorted[n / 2] } else { (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0 }
+}
+
+/// Indices where the mean of `values` shifts, in increasing order, found by binary segmentation. A split is kept if it
+/// lowers the squared error by more than the BIC penalty `2 σ² ln n`, σ being estimated from the median difference of
+/// successive values so the shifts themselves do not inflate it, but never below `min_sigma`: on noise-free values σ
+/// would be 0, and every blip a change. No segment is shorter than `min_segment`.
+pub fn change_points(values: &[f64], min_segment: usize, min_sigma: f64) -> Vec<usize> {
+    let n = values.len();
+    let min_segment = min_segment.max(1);
+    if n < 2 * min_segment {
+        return Vec::new();
+    }
+
+    let mut sum = vec![0.0_f64; n + 1];
+    let mut sum_sq = vec![0.0_f64; n + 1];
+    for (i, v) in values.iter().enumerate() {
+        sum[i + 1] = sum[i] + v;
+        sum_sq[i + 1] = sum_sq[i] + v * v;
+    }
+    // Squared error of values[a..b] around their mean
+    let cost = |a: usize, b: usize| {
+        let s = sum[b] - sum[a];
+        ((sum_sq[b] - sum_sq[a]) - s * s / (b - a) as f64).max(0.0)
+    };
+
+    let differences: Vec<f64> = values.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+    let sigma = (median(&differences) / (0.6745 * std::f64::consts::SQRT_2)).max(min_sigma);
+    // Without noise, only splits that remove more than rounding errors count
+    let penalty = (2.0 * sigma * sigma * (n as f64).ln()).max(1e-9 * cost(0, n));
+
+    let mut points = Vec::<usize>::new();
+    let mut segments = vec![(0, n)];
+    while let Some((a, b)) = segments.pop() {
+        if b - a < 2 * min_segment {
+            continue;
+        }
+
+        let whole = cost(a, b);
+        let (split, gain) = (a + min_segment..=b - min_segment)
+            .map(|split| (split, whole - cost(a, split) - cost(split, b)))
+            .max_by(|x, y| x.1.total_cmp(&y.1))
+            .unwrap();
+
+        if gain > penalty {
+            points.push(split);
+            segments.push((a, split));
+            segments.push((split, b));
+        }
+    }
+
+    points.sort_unstable();
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: f64, expected: f64, tolerance: f64) -> bool {
+        (actual - expected).abs() <= tolerance
+    }
+
+    #[test]
+    fn t_quantile_matches_tables() {
+        for (df, expected) in [(1.0, 12.706), (4.0, 2.776), (10.0, 2.228), (30.0, 2.042), (1000.0, 1.962)] {
+            let t = t_quantile(0.975, df);
+            assert!(close(t, expected, 5e-4), "t(0.975, {df}) = {t}, expected {expected}");
+        }
+    }
+
+    #[test]
+    fn t_cdf_matches_tables() {
+        assert!(close(t_cdf(0.0, 5.0), 0.5, 1e-12));
+        assert!(close(t_cdf(2.0, 10.0), 0.963306, 1e-6));
+        assert!(close(t_cdf(-2.0, 10.0), 1.0 - 0.963306, 1e-6));
+    }
+
+    #[test]
+    fn statistics_of_values() {
+        let stats = Statistics::of(&[1.0, 2.0, 3.0, 4.0]);
+
+        assert_eq!(stats.mean, 2.5);
+        assert_eq!(stats.median, 2.5);
+        assert!(close(stats.std_dev, 1.290994, 1e-6));
+        assert_eq!((stats.min, stats.max), (1.0, 4.0));
+        assert!(Statistics::of(&[7.0]).ci95.is_none());
+    }
+
+    #[test]
+    fn welch_t_test_of_textbook_example() {
+        // Example 1 of the Wikipedia article on Welch's t-test: t = 2.46, df = 25.0, p = 0.021
+        let a = Statistics::of(&[27.5, 21.0, 19.0, 23.6, 17.0, 17.9, 16.9, 20.1, 21.9, 22.6, 23.1, 19.6, 19.0, 21.7, 21.4]);
+        let b = Statistics::of(&[27.1, 22.0, 20.8, 23.4, 23.4, 23.5, 25.8, 22.0, 24.8, 20.2, 21.9, 22.1, 22.9, 20.5, 24.4]);
+        let test = welch_t_test(&a, &b);
+
+        assert!(close(test.t.unwrap(), 2.455356, 1e-5));
+        assert!(close(test.df.unwrap(), 24.988529, 1e-5));
+        assert!(close(test.p_value, 0.021378, 1e-5));
+    }
+
+    #[test]
+    fn welch_t_test_without_variance() {
+        let a = Statistics::of(&[3.0, 3.0, 3.0]);
+
+        let same = welch_t_test(&a, &Statistics::of(&[3.0, 3.0]));
+        assert_eq!((same.t, same.df, same.p_value), (None, None, 1.0));
+
+        let different = welch_t_test(&a, &Statistics::of(&[4.0, 4.0]));
+        assert_eq!((different.t, different.df, different.p_value), (None, None, 0.0));
+    }
+
+    #[test]
+    fn change_points_find_steps() {
+        let mut values = vec![10.0; 20];
+        values.extend([50.0; 30]);
+        values.extend([20.0; 10]);
+
+        assert_eq!(change_points(&values, 3, 0.1), vec![20, 50]);
+    }
+
+    #[test]
+    fn change_points_ignore_flat_values() {
+        assert!(change_points(&[5.0; 40], 3, 0.0).is_empty());
+        assert!(change_points(&[0.0; 40], 3, 0.0).is_empty());
+        // Too short to split into two segments of 3
+        assert!(change_points(&[1.0, 1.0, 1.0, 9.0, 9.0], 3, 0.0).is_empty());
+    }
+
+    #[test]
+    fn change_points_ignore_a_blip_below_the_noise_floor() {
+        let mut values = vec![50.0; 40];
+        values[20] = 50.5;
+
+        // Without a floor the noise-free values have no noise, and the blip splits them
+        assert_eq!(change_points(&values, 3, 0.0).len(), 2);
+        assert!(change_points(&values, 3, 0.5).is_empty());
+    }
+
+    #[test]
+    fn change_points_respect_min_segment() {
+        let mut values = vec![10.0; 30];
+        values[29] = 100.0;
+
+        assert!(change_points(&values, 3, 0.1).iter().all(|p| *p >= 3 && *p <= 27));
+    }
+}
@@ -0,0 +1,365 @@
+use std::ops::Range;
+
+use crate::attribution::{Interval, Machine, Model};
+use crate::export::{Derived, SampleExport};
+use crate::report::{DomainValues, Trim};
+use crate::sample::CollectedPoint;
+use crate::source::Domain;
+use crate::stats;
+
+// Fewest intervals between two changes of the power when looking for the steady state
+const STEADY_MIN_SEGMENT: usize = 3;
+
+// Neighbouring stretches whose mean power differs by less than this fraction belong to the same steady state
+const STEADY_TOLERANCE: f64 = 0.05;
+
+// Noise of the power taken at least as this fraction of its median, so a blip on a noise-free trace is not a change
+const STEADY_NOISE_FLOOR: f64 = 0.01;
+
+/// Which samples at the start and end of a run are left out of the summary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrimMode {
+    // Fractions of the samples to drop at the start and at the end
+    Fraction { start: f64, end: f64 },
+
+    // Seconds to drop after the first sample and before the last
+    Seconds { start: f64, end: f64 },
+
+    // Keep every sample
+    None,
+
+    // Keep the longest stretch of steady package power, found by change-point detection
+    SteadyState
+}
+
+/// How the samples of a run are turned into a summary.
+#[derive(Debug, Clone)]
+pub struct SummaryOptions {
+    pub trim: TrimMode,
+
+    // The machine the samples were taken on
+    pub machine: Machine,
+
+    // Whether there is a target to attribute energy to, false when measuring the whole system
+    pub attribute: bool,
+
+    // Whether to report the energy of every core counter
+    pub per_core: bool,
+
+    pub model: Model,
+
+    // Idle power of the CPU domain in Watts, for models that subtract it
+    pub idle_power: Option<f64>
+}
+
+/// Energy over the measured window of a run.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub duration_ns: u64,
+
+    pub energy: DomainValues,
+
+    pub trim: Trim,
+
+    // Model the energy was attributed with, None without a target
+    pub model: Option<Model>,
+
+    // Samples in the window without the CPU time of the target, left out of the attributed energy
+    pub skipped_samples: usize
+}
+
+impl TrimMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrimMode::Fraction { .. } => "fraction",
+            TrimMode::Seconds { .. } => "seconds",
+            TrimMode::None => "none",
+            TrimMode::SteadyState => "steady-state"
+        }
+    }
+
+    /// Whether the window depends on the times or power of the samples, not only on how many there are.
+    pub fn reads_samples(&self) -> bool {
+        matches!(self, TrimMode::Seconds { .. } | TrimMode::SteadyState)
+    }
+}
+
+// The longest stretch of intervals over which the package power stays within `STEADY_TOLERANCE` of its mean
+fn steady_state(times: &[u64], energy: &[f64]) -> Range<usize> {
+    // Power of every interval, the first ending at sample 1
+    let power: Vec<f64> = (1..times.len()).map(|i| energy[i] / (times[i] - times[i - 1]).max(1) as f64).collect();
+
+    let mut bounds = vec![0];
+    let min_sigma = STEADY_NOISE_FLOOR * stats::Statistics::of(&power).median.abs();
+    bounds.extend(stats::change_points(&power, STEADY_MIN_SEGMENT, min_sigma));
+    bounds.push(power.len());
+
+    // Merge neighbouring stretches with about the same mean power
+    let mean = |a: usize, b: usize| power[a..b].iter().sum::<f64>() / (b - a) as f64;
+    let mut stretches = Vec::<Range<usize>>::new();
+    for segment in bounds.windows(2).map(|b| b[0]..b[1]) {
+        match stretches.last_mut() {
+            Some(last) if (mean(last.start, last.end) - mean(segment.start, segment.end)).abs()
+                <= STEADY_TOLERANCE * mean(last.start, last.end).abs().max(mean(segment.start, segment.end).abs()) => last.end = segment.end,
+            _ => stretches.push(segment)
+        }
+    }
+
+    // Power `k` is of the interval from sample `k` to `k + 1`
+    let longest = stretches.into_iter().max_by_key(|s| (times[s.end] - times[s.start], std::cmp::Reverse(s.start))).unwrap();
+    longest.start + 1..longest.end + 1
+}
+
+impl SummaryOptions {
+    /// The intervals of the measured window of `len` samples, by the index of the sample each ends at. Unless trimming
+    /// a fraction of the samples, their times and package energy are read from `samples` first.
+    pub fn window<I>(&self, samples: I, len: usize) -> Result<Range<usize>, std::io::Error>
+        where I: Iterator<Item = Result<CollectedPoint, std::io::Error>>
+    {
+        let mut times = Vec::<u64>::new();
+        let mut energy = Vec::<f64>::new();
+        if self.trim.reads_samples() {
+            for point in samples {
+                let point = point?;
+                times.push(point.time);
+                energy.push(point.package);
+            }
+        }
+
+        let window = match self.trim {
+            TrimMode::Fraction { start, end } => {
+                (((len as f64) * start).ceil() as usize).max(1)..(len as f64 - (len as f64) * end).floor() as usize
+            }
+            TrimMode::Seconds { start, end } => {
+                let (first, last) = (times[0], times[times.len() - 1]);
+                let start_time = first + (start * 1000000000.0) as u64;
+                let end_time = last.saturating_sub((end * 1000000000.0) as u64);
+
+                // The first interval starting after `start` seconds, and the last ending `end` seconds before the end
+                let start_index = times.iter().position(|t| *t >= start_time).unwrap_or(times.len()) + 1;
+                let end_index = times.iter().rposition(|t| *t <= end_time).map_or(0, |i| i + 1);
+                start_index..end_index
+            }
+            TrimMode::None => 1..len,
+            TrimMode::SteadyState => steady_state(&times, &energy)
+        };
+
+        if window.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Trimming leaves no samples to summarize"));
+        }
+
+        Ok(window)
+    }
+}
+
+/// Computes the summary of the `window` found by `SummaryOptions::window` in a single pass over the samples of a
+/// run, so a trace on disk never has to fit in memory. Every sample is also written to `export`, if given.
+pub fn summarize<I>(samples: I, window: Range<usize>, options: &SummaryOptions, other_domains: &[Domain], mut export: Option<&mut SampleExport>) -> Result<Summary, std::io::Error>
+    where I: Iterator<Item = Result<CollectedPoint, std::io::Error>>
+{
+    let model = options.model.build(&options.machine, options.idle_power)?;
+
+    let mut energy_package_total: f64 = 0.0;
+    let mut energy_cpu_total: f64 = 0.0;
+    let mut energy_cpu_share: f64 = 0.0;
+    let mut energy_other_total: Vec<f64> = vec![0.0; other_domains.len()];
+    let mut energy_socket_total = Vec::<f64>::new();
+    let mut energy_core_total = Vec::<f64>::new();
+    let mut skipped_samples: usize = 0;
+
+    let mut previous: Option<CollectedPoint> = None;
+    let mut start_time: u64 = 0;
+    let mut end_time: u64 = 0;
+
+    for (i, point) in samples.enumerate() {
+        let point = point?;
+
+        // The window runs from the start of its first interval to the end of its last
+        if i == window.start - 1 {
+            start_time = point.time;
+        }
+        if i == window.end - 1 {
+            end_time = point.time;
+        }
+
+        let Some(last) = previous.replace(point) else { continue };
+        let point = previous.as_ref().unwrap();
+
+        let in_window = window.contains(&i);
+        let target_cpu_time = (options.attribute && last.sched != 0 && point.sched != 0).then(|| point.sched - last.sched);
+        let attributed = target_cpu_time.map(|target_cpu_time| model.attribute(&Interval { last: &last, point, target_cpu_time }));
+
+        if let Some(export) = &mut export {
+            export.write(&last, point, Derived { target_cpu_time, attributed, in_window })?;
+        }
+
+        if !in_window {
+            continue;
+        }
+
+        match attributed {
+            Some(attributed) => energy_cpu_share += attributed,
+            None if options.attribute => {
+                skipped_samples += 1;
+                continue;
+            }
+            None => { }
+        }
+
+        energy_package_total += point.package;
+        energy_cpu_total += point.cpu_power;
+        for (total, delta) in energy_other_total.iter_mut().zip(&point.other) {
+            *total += delta;
+        }
+        energy_socket_total.resize(point.sockets.len(), 0.0);
+        for (total, delta) in energy_socket_total.iter_mut().zip(&point.sockets) {
+            *total += delta;
+        }
+        if options.per_core {
+            energy_core_total.resize(point.cores.len(), 0.0);
+            for (total, delta) in energy_core_total.iter_mut().zip(&point.cores) {
+                *total += delta;
+            }
+        }
+    }
+
+    Ok(Summary {
+        duration_ns: end_time - start_time,
+        energy: DomainValues {
+            package: energy_package_total,
+            sockets: energy_socket_total,
+            cpu: energy_cpu_total,
+            cores: energy_core_total,
+            attributed: options.attribute.then_some(energy_cpu_share),
+            domains: other_domains.iter().map(|d| d.name().to_owned()).zip(energy_other_total).collect()
+        },
+        trim: Trim {
+            mode: options.trim.name(),
+            startup_ignore: match options.trim { TrimMode::Fraction { start, .. } => Some(start), _ => None },
+            end_ignore: match options.trim { TrimMode::Fraction { end, .. } => Some(end), _ => None },
+            skip_start: match options.trim { TrimMode::Seconds { start, .. } => Some(start), _ => None },
+            skip_end: match options.trim { TrimMode::Seconds { end, .. } => Some(end), _ => None },
+            first_sample: window.start - 1,
+            last_sample: window.end - 1,
+            // Sample times already count from the start of sampling, before the first sample
+            start_s: start_time as f64 / 1000000000.0,
+            end_s: end_time as f64 / 1000000000.0
+        },
+        model: options.attribute.then(|| model.model()),
+        skipped_samples
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Samples every 100 ms, with the given package energy from the second on
+    fn points(energy: &[f64]) -> Vec<CollectedPoint> {
+        std::iter::once(0.0).chain(energy.iter().copied()).enumerate()
+            .map(|(i, energy)| CollectedPoint {
+                time: i as u64 * 100000000,
+                package: energy,
+                sockets: vec![energy],
+                cpu_power: energy,
+                cores: Vec::new(),
+                other: Vec::new(),
+                sched: 0,
+                sched_cpus: Vec::new(),
+                busy_cpus: Vec::new()
+            })
+            .collect()
+    }
+
+    fn options(trim: TrimMode) -> SummaryOptions {
+        SummaryOptions {
+            trim,
+            machine: Machine::default(),
+            attribute: false,
+            per_core: false,
+            model: Model::CpuTime,
+            idle_power: None
+        }
+    }
+
+    fn window(trim: TrimMode, points: &[CollectedPoint]) -> Result<Range<usize>, std::io::Error> {
+        options(trim).window(points.iter().cloned().map(Ok), points.len())
+    }
+
+    #[test]
+    fn fraction_and_no_trim_windows() {
+        let points = points(&[1.0; 9]);
+
+        assert_eq!(window(TrimMode::Fraction { start: 0.2, end: 0.2 }, &points).unwrap(), 2..8);
+        assert_eq!(window(TrimMode::Fraction { start: 0.0, end: 0.0 }, &points).unwrap(), 1..10);
+        assert_eq!(window(TrimMode::None, &points).unwrap(), 1..10);
+    }
+
+    #[test]
+    fn seconds_window() {
+        // 0 to 1 s
+        let points = points(&[1.0; 10]);
+
+        // From the sample at 0.3 s to the one at 0.7 s
+        assert_eq!(window(TrimMode::Seconds { start: 0.25, end: 0.25 }, &points).unwrap(), 4..8);
+        // Exactly on a sample
+        assert_eq!(window(TrimMode::Seconds { start: 0.2, end: 0.2 }, &points).unwrap(), 3..9);
+        assert_eq!(window(TrimMode::Seconds { start: 0.0, end: 0.0 }, &points).unwrap(), 1..11);
+        // Only the first interval ends 0.85 s before the last sample
+        assert_eq!(window(TrimMode::Seconds { start: 0.0, end: 0.85 }, &points).unwrap(), 1..2);
+    }
+
+    #[test]
+    fn seconds_covering_the_run_are_an_error() {
+        let points = points(&[1.0; 10]);
+
+        for (start, end) in [(0.6, 0.6), (1.0, 0.0), (0.0, 1.0), (0.0, 0.95), (5.0, 0.0)] {
+            let error = window(TrimMode::Seconds { start, end }, &points).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn steady_state_skips_ramps() {
+        // 10 intervals ramping up, 60 at 6 J (60 W), 8 winding down, with a little noise
+        let mut energy: Vec<f64> = (0..10).map(|i| 1.0 + 0.5 * i as f64).collect();
+        energy.extend((0..60).map(|i| 6.0 + 0.05 * ((i * 7 % 5) as f64 - 2.0)));
+        energy.extend([1.5; 8]);
+
+        assert_eq!(window(TrimMode::SteadyState, &points(&energy)).unwrap(), 11..71);
+    }
+
+    #[test]
+    fn steady_state_of_flat_power_is_the_whole_run() {
+        assert_eq!(window(TrimMode::SteadyState, &points(&[0.0; 20])).unwrap(), 1..21);
+
+        let mut energy = vec![5.0; 40];
+        energy[20] = 5.05;
+        assert_eq!(window(TrimMode::SteadyState, &points(&energy)).unwrap(), 1..41);
+    }
+
+    #[test]
+    fn summary_covers_the_window() {
+        let points = points(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        let options = options(TrimMode::None);
+        let summary = summarize(points.iter().cloned().map(Ok), 2..5, &options, &[], None).unwrap();
+
+        // Intervals ending at samples 2 to 4, from 0.1 s to 0.4 s
+        assert_eq!(summary.energy.package, 2.0 + 3.0 + 4.0);
+        assert_eq!(summary.duration_ns, 300000000);
+        assert_eq!((summary.trim.first_sample, summary.trim.last_sample), (1, 4));
+        assert_eq!((summary.trim.start_s, summary.trim.end_s), (0.1, 0.4));
+    }
+
+    #[test]
+    fn window_times_count_from_the_start_of_sampling() {
+        // The first sample is taken 50 ms after sampling started
+        let points: Vec<CollectedPoint> = points(&[1.0, 2.0, 3.0, 4.0, 5.0]).into_iter()
+            .map(|p| CollectedPoint { time: p.time + 50000000, ..p })
+            .collect();
+        let summary = summarize(points.iter().cloned().map(Ok), 2..5, &options(TrimMode::None), &[], None).unwrap();
+
+        assert_eq!(summary.duration_ns, 300000000);
+        assert_eq!((summary.trim.start_s, summary.trim.end_s), (0.15, 0.45));
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::util::read_sysfs;
+
+const CPU_SYSFS_ROOT: &str = "/sys/devices/system/cpu";
+
+/// A physical package (socket) and its online logical CPUs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    // physical_package_id as reported by the kernel
+    pub id: u32,
+
+    // Sorted, never empty
+    pub cpus: Vec<usize>
+}
+
+/// A physical core and its online SMT siblings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Core {
+    pub package_id: u32,
+
+    // core_id is only unique within a package
+    pub core_id: u32,
+
+    // Sorted, never empty
+    pub cpus: Vec<usize>
+}
+
+/// CPU topology read from `/sys/devices/system/cpu`. Offline CPUs are left out.
+#[derive(Debug, Clone)]
+pub struct Topology {
+    // Sorted by package id
+    packages: Vec<Package>,
+
+    // Sorted by package id, then core id
+    cores: Vec<Core>
+}
+
+/// Parses a kernel CPU list such as "0,28" or "0-3,8-11".
+pub fn parse_cpu_list(content: &str) -> Vec<usize> {
+    let mut cpus = Vec::<usize>::new();
+
+    for range in content.trim().split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => {
+                if let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) {
+                    cpus.extend(start..=end);
+                }
+            }
+            None => {
+                if let Ok(cpu) = range.parse::<usize>() {
+                    cpus.push(cpu);
+                }
+            }
+        }
+    }
+
+    cpus
+}
+
+impl Topology {
+    pub fn detect() -> Result<Topology, std::io::Error> {
+        Topology::with_root(CPU_SYSFS_ROOT)
+    }
+
+    /// Reads the topology below a sysfs CPU root, usually `/sys/devices/system/cpu`.
+    pub fn with_root(root: impl AsRef<Path>) -> Result<Topology, std::io::Error> {
+        let root: PathBuf = root.as_ref().to_path_buf();
+        let online = parse_cpu_list(&fs::read_to_string(root.join("online"))?);
+
+        let mut packages = Vec::<Package>::new();
+        let mut cores = Vec::<Core>::new();
+
+        for cpu in online {
+            let package_id = read_sysfs::<u32>(&root.join(format!("cpu{cpu}/topology/physical_package_id")))?;
+            let core_id = read_sysfs::<u32>(&root.join(format!("cpu{cpu}/topology/core_id")))?;
+
+            match packages.iter_mut().find(|p| p.id == package_id) {
+                Some(package) => package.cpus.push(cpu),
+                None => packages.push(Package {
+                    id: package_id,
+                    cpus: vec![cpu]
+                })
+            }
+
+            match cores.iter_mut().find(|c| c.package_id == package_id && c.core_id == core_id) {
+                Some(core) => core.cpus.push(cpu),
+                None => cores.push(Core {
+                    package_id,
+                    core_id,
+                    cpus: vec![cpu]
+                })
+            }
+        }
+
+        if packages.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No online CPUs under {}", root.display())));
+        }
+
+        packages.sort_by_key(|p| p.id);
+        cores.sort_by_key(|c| (c.package_id, c.core_id));
+
+        Ok(Topology { packages, cores })
+    }
+
+    pub fn packages(&self) -> &[Package] { &self.packages }
+
+    pub fn cores(&self) -> &[Core] { &self.cores }
+
+    /// Index in `cores()` of the physical core a logical CPU belongs to.
+    pub fn core_of(&self, cpu: usize) -> Option<usize> {
+        self.cores.iter().position(|c| c.cpus.contains(&cpu))
+    }
+
+    /// The first online SMT sibling of every physical core, where per-core MSRs are read.
+    pub fn core_cpus(&self) -> Vec<usize> {
+        self.cores.iter().map(|c| c.cpus[0]).collect()
+    }
+
+    /// Index in `packages()` of the package a logical CPU belongs to.
+    pub fn package_of(&self, cpu: usize) -> Option<usize> {
+        self.packages.iter().position(|p| p.cpus.contains(&cpu))
+    }
+
+    /// The first online CPU of every package, where package-scoped MSRs are read.
+    pub fn package_cpus(&self) -> Vec<usize> {
+        self.packages.iter().map(|p| p.cpus[0]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sysfs CPU root with the given (package, core) of every CPU, of which `online` are online
+    fn fake_root(name: &str, online: &str, cpus: &[(u32, u32)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("powermeter-test-{name}-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("online"), format!("{online}\n")).unwrap();
+
+        for (cpu, (package_id, core_id)) in cpus.iter().enumerate() {
+            let topology = root.join(format!("cpu{cpu}/topology"));
+            fs::create_dir_all(&topology).unwrap();
+            fs::write(topology.join("physical_package_id"), format!("{package_id}\n")).unwrap();
+            fs::write(topology.join("core_id"), format!("{core_id}\n")).unwrap();
+        }
+
+        root
+    }
+
+    #[test]
+    fn cpu_lists() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("5"), vec![5]);
+        assert_eq!(parse_cpu_list(""), Vec::<usize>::new());
+        assert_eq!(parse_cpu_list("\n"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn two_packages_with_smt_siblings() {
+        // Two packages of two cores each, with the siblings numbered after all first threads like on most x86
+        // servers, and the second thread of the last core offline
+        let cpus = [(0, 0), (0, 1), (1, 0), (1, 1), (0, 0), (0, 1), (1, 0), (1, 1)];
+        let root = fake_root("topology", "0-6", &cpus);
+        let topology = Topology::with_root(&root).unwrap();
+
+        assert_eq!(topology.packages(), &[
+            Package { id: 0, cpus: vec![0, 1, 4, 5] },
+            Package { id: 1, cpus: vec![2, 3, 6] }
+        ]);
+        assert_eq!(topology.cores(), &[
+            Core { package_id: 0, core_id: 0, cpus: vec![0, 4] },
+            Core { package_id: 0, core_id: 1, cpus: vec![1, 5] },
+            Core { package_id: 1, core_id: 0, cpus: vec![2, 6] },
+            Core { package_id: 1, core_id: 1, cpus: vec![3] }
+        ]);
+        assert_eq!(topology.core_cpus(), vec![0, 1, 2, 3]);
+        assert_eq!(topology.package_cpus(), vec![0, 2]);
+        assert_eq!(topology.core_of(6), Some(2));
+        assert_eq!(topology.package_of(5), Some(0));
+        assert_eq!(topology.core_of(7), None);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
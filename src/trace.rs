@@ -0,0 +1,479 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::baseline::Baseline;
+use crate::process::Accounting;
+use crate::report::{ExitStatus, Host};
+use crate::sample::CollectedPoint;
+use crate::source::{self, Domain, EnergySource, Units};
+use crate::util::invalid_data;
+
+/// Version of the trace layout. Traces of another version are refused.
+pub const TRACE_VERSION: u32 = 1;
+
+/// How to turn raw readings of one domain into Joules.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CounterInfo {
+    pub domain: Domain,
+
+    // Joules per count
+    pub unit: f64,
+
+    // Highest raw value before the counter wraps around to 0
+    pub max: u64,
+
+    // Shortest time in which the counter can wrap around, in seconds, if the maximum power is known
+    pub wrap_interval: Option<f64>
+}
+
+/// First record of a trace: everything needed to interpret the raw readings without the hardware.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceHeader {
+    pub version: u32,
+
+    // Seconds since the Unix epoch when sampling started
+    pub start_time: u64,
+
+    pub host: Host,
+
+    pub command: Option<String>,
+
+    // Process that was measured
+    pub pid: Option<u32>,
+
+    // Whether the whole system was measured, without a target to attribute energy to
+    #[serde(default)]
+    pub system_wide: bool,
+
+    pub accounting: Option<Accounting>,
+
+    pub cgroup: Option<PathBuf>,
+
+    pub period_ms: u64,
+
+    // As decoded from the power unit register
+    pub units: Units,
+
+    // Package, then Core if the source has it, then the extra domains in `other_domains` order
+    pub counters: Vec<CounterInfo>,
+
+    pub other_domains: Vec<Domain>,
+
+    // Index of the core counter, or the package without one, covering every logical CPU
+    #[serde(default)]
+    pub counter_of_cpu: Vec<Option<usize>>,
+
+    // Index of the physical core of every logical CPU
+    #[serde(default)]
+    pub core_of_cpu: Vec<Option<usize>>
+}
+
+/// Raw counter readings and target CPU time of one sample.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawSample {
+    // Nanoseconds since sampling started
+    pub time: u64,
+
+    // Cumulative CPU time of the target, in nanoseconds, 0 if it could not be read
+    pub sched: u64,
+
+    // Per socket
+    pub package: Vec<u64>,
+
+    // Per core counter, empty without a core counter
+    pub cores: Vec<u64>,
+
+    // Per extra domain, then per socket
+    pub other: Vec<Vec<u64>>,
+
+    // Cumulative CPU time of the target and busy time per logical CPU, in nanoseconds, empty without a target
+    #[serde(default)]
+    pub sched_cpus: Vec<u64>,
+
+    #[serde(default)]
+    pub busy_cpus: Vec<u64>
+}
+
+/// One line of a trace file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "lowercase")]
+pub enum TraceRecord {
+    Header(Box<TraceHeader>),
+
+    Sample(RawSample),
+
+    // Idle power measured before or after the target ran
+    Baseline(Box<Baseline>),
+
+    // How the target exited, last line of a complete trace
+    Exit(ExitStatus),
+
+    // Last line of a complete trace that stopped before the target exited, or whose exit status is unknown
+    Stop
+}
+
+/// Writes a trace as JSON lines: a header, the raw samples, then the exit status of the target.
+#[derive(Debug)]
+pub struct TraceWriter {
+    writer: BufWriter<File>
+}
+
+/// A trace file opened for analysis. Samples are read back from the file when iterated over.
+#[derive(Debug)]
+pub struct Trace {
+    path: PathBuf,
+
+    pub header: TraceHeader,
+
+    // Samples after the baseline reading, i.e. the number of `CollectedPoint`s
+    pub len: usize,
+
+    pub exit_status: Option<ExitStatus>,
+
+    // Idle baseline over all calibration windows, if calibrated
+    pub baseline: Option<Baseline>,
+
+    // Whether the trace ends with an exit or stop record, rather than being cut short
+    pub complete: bool,
+
+    // Samples taken further apart than the package or core wrap interval
+    pub possible_multiple_wraps: usize
+}
+
+impl CounterInfo {
+    pub fn of(ctx: &dyn EnergySource, domain: Domain) -> CounterInfo {
+        CounterInfo {
+            domain,
+            unit: ctx.energy_unit(domain),
+            max: ctx.counter_max(domain),
+            wrap_interval: ctx.wrap_interval(domain).map(|d| d.as_secs_f64())
+        }
+    }
+
+    /// Energy counted between two raw readings, in Joules, allowing for one wraparound.
+    pub fn delta(&self, before: u64, after: u64) -> f64 {
+        source::counter_delta(self.max, before, after) as f64 * self.unit
+    }
+
+    fn deltas(&self, before: &[u64], after: &[u64]) -> Vec<f64> {
+        before.iter().zip(after).map(|(b, a)| self.delta(*b, *a)).collect()
+    }
+}
+
+impl TraceHeader {
+    pub fn counter(&self, domain: Domain) -> Option<&CounterInfo> {
+        self.counters.iter().find(|c| c.domain == domain)
+    }
+
+    pub fn has_core(&self) -> bool {
+        self.counter(Domain::Core).is_some()
+    }
+
+    /// The sample of the energy used between two raw samples, as the measurement loop computes it.
+    pub fn point(&self, before: &RawSample, after: &RawSample) -> Result<CollectedPoint, std::io::Error> {
+        let package = self.counter(Domain::Package).ok_or_else(|| invalid_data("Trace has no package counter"))?;
+        let sockets = package.deltas(&before.package, &after.package);
+        let package_sum: f64 = sockets.iter().sum();
+
+        let cores = match self.counter(Domain::Core) {
+            Some(core) => core.deltas(&before.cores, &after.cores),
+            None => Vec::new()
+        };
+
+        let mut other = Vec::<f64>::with_capacity(self.other_domains.len());
+        for ((domain, b), a) in self.other_domains.iter().zip(&before.other).zip(&after.other) {
+            let counter = self.counter(*domain).ok_or_else(|| invalid_data(format!("Trace has no {domain} counter")))?;
+            other.push(counter.deltas(b, a).iter().sum());
+        }
+
+        Ok(CollectedPoint {
+            time: after.time,
+            package: package_sum,
+            sockets,
+            cpu_power: if self.has_core() { cores.iter().sum() } else { package_sum },
+            cores,
+            other,
+            sched: after.sched,
+            sched_cpus: after.sched_cpus.clone(),
+            busy_cpus: after.busy_cpus.clone()
+        })
+    }
+
+    // Whether two samples are too far apart to tell one wraparound of the package or core counter from several
+    fn possible_multiple_wraps(&self, before: &RawSample, after: &RawSample) -> bool {
+        let elapsed = (after.time - before.time) as f64 / 1000000000.0;
+
+        [Domain::Package, Domain::Core].iter()
+            .filter_map(|d| self.counter(*d))
+            .any(|c| c.wrap_interval.is_some_and(|t| elapsed >= t))
+    }
+}
+
+impl TraceWriter {
+    pub fn create(path: impl AsRef<Path>, header: TraceHeader) -> Result<TraceWriter, std::io::Error> {
+        let mut trace = TraceWriter {
+            writer: BufWriter::new(File::create(path)?)
+        };
+        trace.write(&TraceRecord::Header(Box::new(header)))?;
+
+        Ok(trace)
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> Result<(), std::io::Error> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        writeln!(self.writer)
+    }
+
+    pub fn sample(&mut self, sample: RawSample) -> Result<(), std::io::Error> {
+        self.write(&TraceRecord::Sample(sample))
+    }
+
+    pub fn baseline(&mut self, baseline: &Baseline) -> Result<(), std::io::Error> {
+        self.write(&TraceRecord::Baseline(Box::new(baseline.clone())))
+    }
+
+    /// Records how the target exited, if known, and flushes the trace.
+    pub fn finish(mut self, exit_status: Option<ExitStatus>) -> Result<(), std::io::Error> {
+        match exit_status {
+            Some(status) => self.write(&TraceRecord::Exit(status))?,
+            None => self.write(&TraceRecord::Stop)?
+        }
+        self.writer.flush()
+    }
+}
+
+fn records(path: &Path) -> Result<impl Iterator<Item = Result<TraceRecord, std::io::Error>>, std::io::Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut lines = reader.lines()
+        .filter(|line| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .peekable();
+
+    Ok(std::iter::from_fn(move || {
+        let line = match lines.next()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e))
+        };
+
+        match serde_json::from_str::<TraceRecord>(&line) {
+            Ok(record) => Some(Ok(record)),
+            // A recording that was killed can leave the last line half written
+            Err(_) if lines.peek().is_none() => None,
+            Err(e) => Some(Err(invalid_data(e)))
+        }
+    }))
+}
+
+impl Trace {
+    /// Reads the header of a trace and scans it once to count the samples.
+    pub fn open(path: impl AsRef<Path>) -> Result<Trace, std::io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut records = records(&path)?;
+
+        let header = match records.next() {
+            Some(Ok(TraceRecord::Header(header))) => *header,
+            Some(Err(e)) => return Err(e),
+            _ => return Err(invalid_data(format!("{} does not start with a trace header", path.display())))
+        };
+
+        if header.version != TRACE_VERSION {
+            return Err(invalid_data(format!("{} is a version {} trace, only version {TRACE_VERSION} is supported", path.display(), header.version)));
+        }
+
+        let mut samples: usize = 0;
+        let mut exit_status = None;
+        let mut baselines = Vec::<Baseline>::new();
+        let mut complete = false;
+        let mut possible_multiple_wraps: usize = 0;
+        let mut previous: Option<RawSample> = None;
+
+        for record in records {
+            match record? {
+                TraceRecord::Sample(sample) => {
+                    if previous.as_ref().is_some_and(|p| header.possible_multiple_wraps(p, &sample)) {
+                        possible_multiple_wraps += 1;
+                    }
+                    previous = Some(sample);
+                    samples += 1;
+                }
+                TraceRecord::Baseline(baseline) => baselines.push(*baseline),
+                TraceRecord::Exit(status) => {
+                    exit_status = Some(status);
+                    complete = true;
+                }
+                TraceRecord::Stop => complete = true,
+                TraceRecord::Header(_) => return Err(invalid_data(format!("{} has more than one header", path.display())))
+            }
+        }
+
+        Ok(Trace {
+            path,
+            header,
+            len: samples.saturating_sub(1),
+            exit_status,
+            baseline: Baseline::combine(&baselines),
+            complete,
+            possible_multiple_wraps
+        })
+    }
+
+    /// The samples of the trace, computed from consecutive raw readings as the measurement loop does.
+    pub fn points(&self) -> Result<impl Iterator<Item = Result<CollectedPoint, std::io::Error>> + '_, std::io::Error> {
+        let mut previous: Option<RawSample> = None;
+
+        Ok(records(&self.path)?.filter_map(move |record| {
+            let sample = match record {
+                Ok(TraceRecord::Sample(sample)) => sample,
+                Ok(_) => return None,
+                Err(e) => return Some(Err(e))
+            };
+
+            let before = previous.replace(sample)?;
+
+            Some(self.header.point(&before, previous.as_ref().unwrap()))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+
+    use super::*;
+    use crate::report::DomainValues;
+
+    // 2^-14 J and 2^-16 J, so the deltas are exact
+    const UNIT: f64 = 1.0 / 16384.0;
+    const DRAM_UNIT: f64 = 1.0 / 65536.0;
+    const MAX: u64 = 0xFFFF_FFFF;
+
+    fn header() -> TraceHeader {
+        let counter = |domain, unit| CounterInfo { domain, unit, max: MAX, wrap_interval: Some(60.0) };
+
+        TraceHeader {
+            version: TRACE_VERSION,
+            start_time: 1700000000,
+            host: Host {
+                hostname: "test".to_owned(),
+                kernel: "6.1.0".to_owned(),
+                cpu: "GenuineIntel family 0x06 model 0x55".to_owned(),
+                vendor_id: "GenuineIntel".to_owned(),
+                family: 6,
+                model: 0x55,
+                cpus: 2,
+                backend: "intel-msr".to_owned(),
+                powermeter_version: "0.0.0".to_owned()
+            },
+            command: Some("sleep 1".to_owned()),
+            pid: Some(1234),
+            system_wide: false,
+            accounting: Some(Accounting::Proc),
+            cgroup: None,
+            period_ms: 100,
+            units: Units { time: 0.0009765625, energy: UNIT, power: 0.125 },
+            counters: vec![counter(Domain::Package, UNIT), counter(Domain::Core, UNIT), counter(Domain::Dram, DRAM_UNIT)],
+            other_domains: vec![Domain::Dram],
+            counter_of_cpu: vec![Some(0), Some(0)],
+            core_of_cpu: vec![Some(0), Some(0)]
+        }
+    }
+
+    fn raw(time: u64, package: u64, core: u64, dram: u64) -> RawSample {
+        RawSample {
+            time,
+            sched: time / 2,
+            package: vec![package],
+            cores: vec![core],
+            other: vec![vec![dram]],
+            sched_cpus: vec![time / 2, 0],
+            busy_cpus: vec![time, time / 4]
+        }
+    }
+
+    fn baseline(duration_ns: u64, package: f64) -> Baseline {
+        Baseline {
+            duration_ns,
+            power: DomainValues { package, cpu: package / 2.0, ..DomainValues::default() }
+        }
+    }
+
+    // A trace with a baseline reading, two samples, the second of which wraps the package counter, and two
+    // calibration windows
+    fn write_trace(path: &Path) -> TraceWriter {
+        let mut trace = TraceWriter::create(path, header()).unwrap();
+
+        trace.sample(raw(0, MAX - 16383, 1000, 0)).unwrap();
+        trace.baseline(&baseline(1_000_000_000, 4.0)).unwrap();
+        trace.sample(raw(100_000_000, MAX, 1000 + 8192, 65536)).unwrap();
+        trace.sample(raw(200_000_000, 16384, 1000 + 16384, 2 * 65536)).unwrap();
+        trace.baseline(&baseline(3_000_000_000, 8.0)).unwrap();
+
+        trace
+    }
+
+    fn trace_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("powermeter-test-{name}-{}.jsonl", std::process::id()))
+    }
+
+    fn points(trace: &Trace) -> Vec<CollectedPoint> {
+        trace.points().unwrap().collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = trace_path("trace");
+        write_trace(&path).finish(Some(ExitStatus { code: Some(0), signal: None })).unwrap();
+        let trace = Trace::open(&path).unwrap();
+
+        assert_eq!(trace.header.command.as_deref(), Some("sleep 1"));
+        assert_eq!(trace.header.other_domains, vec![Domain::Dram]);
+        assert_eq!(trace.len, 2);
+        assert!(trace.complete);
+        assert_eq!(trace.exit_status.as_ref().and_then(|s| s.code), Some(0));
+        assert_eq!(trace.possible_multiple_wraps, 0);
+
+        // Weighted by length: 4 W for 1 s and 8 W for 3 s
+        let baseline = trace.baseline.as_ref().unwrap();
+        assert_eq!(baseline.duration_ns, 4_000_000_000);
+        assert!((baseline.power.package - 7.0).abs() < 1e-12);
+
+        let points = points(&trace);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].time, 100_000_000);
+        assert_eq!(points[0].package, 16383.0 * UNIT);
+        assert_eq!(points[0].cores, vec![0.5]);
+        assert_eq!(points[0].cpu_power, 0.5);
+        assert_eq!(points[0].other, vec![1.0]);
+        assert_eq!(points[0].sched, 50_000_000);
+        assert_eq!(points[0].busy_cpus, vec![100_000_000, 25_000_000]);
+        // From the maximum over the wraparound to 16384 counts
+        assert_eq!(points[1].package, 16385.0 * UNIT);
+        assert_eq!(points[1].other, vec![1.0]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cut_off_last_line() {
+        let path = trace_path("trace-cut-off");
+        drop(write_trace(&path));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"record\":\"sample\",\"time\":300000000,\"sched\":15").unwrap();
+        drop(file);
+
+        let trace = Trace::open(&path).unwrap();
+
+        assert_eq!(trace.len, 2);
+        assert!(!trace.complete);
+        assert!(trace.exit_status.is_none());
+        assert_eq!(points(&trace).len(), 2);
+
+        // Anywhere else, a malformed line is an error
+        fs::write(&path, fs::read_to_string(&path).unwrap().replacen("\"record\":\"baseline\"", "\"record\":\"bogus\"", 1)).unwrap();
+        assert_eq!(Trace::open(&path).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        fs::remove_file(path).unwrap();
+    }
+}
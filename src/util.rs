@@ -0,0 +1,23 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// An `InvalidData` error with the given message.
+pub(crate) fn invalid_data(msg: impl fmt::Display) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reads a sysfs attribute holding a single value. The error names the path if it does not parse.
+pub(crate) fn read_sysfs<T>(path: &Path) -> Result<T, std::io::Error>
+    where T: FromStr, T::Err: fmt::Display
+{
+    let content = fs::read_to_string(path)?;
+
+    content.trim().parse::<T>().map_err(|e| invalid_data(format!("{}: {e}", path.display())))
+}
+
+/// Clock ticks per second, the unit of the CPU times in `/proc`.
+pub(crate) fn clock_ticks_per_second() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64
+}